@@ -0,0 +1,84 @@
+use std::{io::{self, Read, Write}, thread};
+
+use taskmastersocket::{TaskmasterClient, ClientError, ClientResult, LogStream, DEFAULT_SOCKET_PATH};
+
+// ctrl-], so that ctrl-d and ctrl-c reach the process
+const DETACH: u8 = 0x1d;
+
+struct RawInput {
+	original: libc::termios,
+}
+
+impl RawInput {
+	// None when stdin is not a terminal, its input is then sent as is until its end
+	fn enter() -> Option<RawInput> {
+		let mut original: libc::termios = unsafe { std::mem::zeroed() };
+		if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 || unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+			return None;
+		}
+
+		// Bytes are sent as they are typed, still echoed since the process does not echo a pipe
+		let mut raw = original;
+		raw.c_lflag &= !(libc::ICANON | libc::ISIG);
+		raw.c_cc[libc::VMIN] = 1;
+		raw.c_cc[libc::VTIME] = 0;
+		if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+			return None;
+		}
+		Some(RawInput { original })
+	}
+}
+
+impl Drop for RawInput {
+	fn drop(&mut self) {
+		unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+	}
+}
+
+// Keys typed are sent to the process until ctrl-], its output is printed as it comes
+pub fn run(id: usize, process: usize) -> ClientResult<()> {
+	// A connection of its own, the main one is still there once detached
+	let attachment = TaskmasterClient::connect(DEFAULT_SOCKET_PATH)?.attach(id, process)?;
+	let mut input = attachment.input()?;
+	println!("\x1b[90m{}, ctrl-] to detach\x1b[0m", attachment.message);
+
+	let output = thread::spawn(move || -> ClientResult<()> {
+		for item in attachment {
+			match item {
+				Ok((LogStream::Stdout, data)) => {
+					let _ = io::stdout().write_all(&data);
+					let _ = io::stdout().flush();
+				}
+				Ok((LogStream::Stderr, data)) => {
+					let _ = io::stderr().write_all(&data);
+				}
+				Err(ClientError::Daemon(err)) => eprintln!("\x1b[91mError\x1b[0m: {err}"),
+				Err(err) => return Err(err),
+			}
+		}
+		Ok(())
+	});
+
+	let raw = RawInput::enter();
+	let mut buffer = [0u8; 4096];
+	loop {
+		let len = match io::stdin().lock().read(&mut buffer) {
+			Ok(0) => break,
+			Ok(len) => len,
+			Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+			Err(_) => break,
+		};
+		let detach = buffer[..len].iter().position(|&byte| byte == DETACH);
+		let data = &buffer[..detach.unwrap_or(len)];
+		if !data.is_empty() {
+			input.send(data)?;
+		}
+		if detach.is_some() {
+			break;
+		}
+	}
+	drop(raw);
+	println!();
+	input.detach()?;
+	output.join().unwrap_or(Ok(()))
+}
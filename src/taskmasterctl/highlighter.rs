@@ -9,7 +9,7 @@ fn rgb(color: u64) -> Option<Color> {
 	Some(Color{
 		r: ((color >> 16) & 0xff) as u8,
 		g: ((color >> 8) & 0xff) as u8,
-		b: ((color >> 0) & 0xff) as u8,
+		b: (color & 0xff) as u8,
 		a: 255
 	})
 }
@@ -55,7 +55,7 @@ scope: taskmasterctl
 
 contexts:
   main:
-  - match: \b(help|status|start|stop|restart|info|load|unload|reload|logs)\b
+  - match: \b(help|status|start|stop|restart|info|load|unload|reload|logs|top|watch|send|attach|signal|daemon|upgrade|scale)\b
     scope: function
   - match: \bglobal\b
     scope: keyword
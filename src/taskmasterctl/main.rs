@@ -1,8 +1,10 @@
 extern crate taskmastersocket;
-use taskmastersocket::{TaskmasterDaemonRequest, TaskmasterDaemonResult};
+use taskmastersocket::{TaskmasterDaemonRequest, TaskmasterDaemonResult, TaskmasterClient, ClientError, LogStream, DEFAULT_SOCKET_PATH, signal_names};
 
 mod highlighter;
 use highlighter::{TaskmasterHighlighter};
+mod top;
+mod attach;
 
 use rustyline::{
 	highlight::Highlighter,
@@ -14,9 +16,6 @@ use rustyline::{
 
 use std::{borrow::Cow::{self, Owned}, path::PathBuf, fs};
 
-use std::io::{Write};
-use std::os::unix::net::UnixStream;
-
 enum Status {
 	None,
 	Success,
@@ -38,6 +37,16 @@ impl Completer for TaskmasterHelper {
 			pos: usize,
 			ctx: &rustyline::Context<'_>,
 		) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
+		// Signal names right after signal
+		if let Some(prefix) = line[..pos].trim_start().strip_prefix("signal ").map(str::trim_start) {
+			if !prefix.contains(char::is_whitespace) {
+				let candidates = signal_names().into_iter()
+					.filter(|name| name.starts_with(&prefix.to_ascii_uppercase()))
+					.map(|name| completion::Pair { display: name.clone(), replacement: name })
+					.collect();
+				return Ok((pos - prefix.len(), candidates));
+			}
+		}
 		self.completion.complete(line, pos, ctx)
 	}
 	fn update(&self, line: &mut LineBuffer, start: usize, elected: &str) {
@@ -49,7 +58,7 @@ impl Completer for TaskmasterHelper {
 impl Hinter for TaskmasterHelper {
 	type Hint = String;
 
-	fn hint(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> Option<String> {
+	fn hint(&self, _line: &str, _pos: usize, _ctx: &rustyline::Context<'_>) -> Option<String> {
 		None
 	}
 }
@@ -62,7 +71,7 @@ impl Highlighter for TaskmasterHelper {
 	fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
 			&'s self,
 			prompt: &'p str,
-			default: bool,
+			_default: bool,
 		) -> Cow<'b, str> {
 		match self.status {
 			Status::None => Owned("\x1b[1;94m".to_owned() + prompt + "\x1b[0m"),
@@ -81,7 +90,7 @@ impl Highlighter for TaskmasterHelper {
 }
 
 impl Validator for TaskmasterHelper {
-	fn validate(&self, ctx: &mut validate::ValidationContext) -> rustyline::Result<validate::ValidationResult> {
+	fn validate(&self, _ctx: &mut validate::ValidationContext) -> rustyline::Result<validate::ValidationResult> {
 		use validate::ValidationResult::{Valid};
 
 		Ok(Valid(None))
@@ -101,24 +110,64 @@ fn resolve_path(path: &str) -> Result<String, &str> {
 fn usage() {
 	println!("Usage:");
 	print!("{}", TaskmasterHighlighter::new().highlight(r#"
-  status
+  status [--tree]
+  top
   reload
   restart
   stop
+  daemon upgrade
 
   start <task-id>
   stop <task-id>
   restart <task-id>
-  info <task-id>
+  restart --rolling [--batch N] [--wait-ready] <task-id>
+  info <task-id> [--tree]
+  logs <task-id>[:<process>] [stdout|stderr] [lines]
+  send <task-id>[:<process>] <text>
+  attach <task-id>[:<process>]
+  signal <signal> <task-id>[:<process>]
+  scale <program> <numprocs|reset>
 
   load <file>
   unload <file>
 "#));
 }
 
+// <task-id> or <task-id>:<process>
+fn parse_selector(selector: &str) -> Result<(usize, Option<usize>), &'static str> {
+	let (id, process) = match selector.split_once(':') {
+		Some((id, process)) => (id, Some(process)),
+		None => (selector, None),
+	};
+	Ok((
+		id.parse::<usize>().map_err(|_| "Argument should be an int")?,
+		process.map(|process| process.parse::<usize>().map_err(|_| "Process should be an int")).transpose()?,
+	))
+}
+
+// The flags of a rolling restart, in any order around the task id
+fn parse_rolling(args: &[&str]) -> Result<TaskmasterDaemonRequest, &'static str> {
+	let (mut id, mut batch, mut wait_ready) = (None, 1, false);
+	let mut args = args.iter();
+
+	while let Some(arg) = args.next() {
+		match *arg {
+			"--rolling" => {}
+			"--wait-ready" => wait_ready = true,
+			"--batch" => batch = args.next()
+				.and_then(|n| n.parse::<usize>().ok())
+				.filter(|n| *n > 0)
+				.ok_or("Batch should be a positive int")?,
+			arg => id = Some(arg.parse::<usize>().map_err(|_| "Argument should be an int")?),
+		}
+	}
+	Ok(TaskmasterDaemonRequest::RestartTaskRolling{id: id.ok_or("Expected a task id")?, batch, wait_ready})
+}
+
 fn parse_line(line: &str) -> Result<TaskmasterDaemonRequest, &str> {
 	Ok(match line {
 		"status" => TaskmasterDaemonRequest::Status,
+		"status --tree" => TaskmasterDaemonRequest::StatusTree,
 		"reload" => TaskmasterDaemonRequest::Reload,
 		"restart" => TaskmasterDaemonRequest::Restart,
 		"stop" => TaskmasterDaemonRequest::Stop,
@@ -130,12 +179,51 @@ fn parse_line(line: &str) -> Result<TaskmasterDaemonRequest, &str> {
 			}
 			
 			match parts[0] {
-				"start" => TaskmasterDaemonRequest::StartTask(usize::from_str_radix(parts[1], 10).map_err(|_| "Argument should be an int")?),
-				"stop" => TaskmasterDaemonRequest::StopTask(usize::from_str_radix(parts[1], 10).map_err(|_| "Argument should be an int")?),
-				"restart" => TaskmasterDaemonRequest::RestartTask(usize::from_str_radix(parts[1], 10).map_err(|_| "Argument should be an int")?),
-				"info" => TaskmasterDaemonRequest::InfoTask(usize::from_str_radix(parts[1], 10).map_err(|_| "Argument should be an int")?),
+				"start" => TaskmasterDaemonRequest::StartTask(parts[1].parse::<usize>().map_err(|_| "Argument should be an int")?),
+				"stop" => TaskmasterDaemonRequest::StopTask(parts[1].parse::<usize>().map_err(|_| "Argument should be an int")?),
+				"restart" if parts.contains(&"--rolling") => parse_rolling(&parts[1..])?,
+				"restart" => TaskmasterDaemonRequest::RestartTask(parts[1].parse::<usize>().map_err(|_| "Argument should be an int")?),
+				"info" => {
+					let id = parts[1].parse::<usize>().map_err(|_| "Argument should be an int")?;
+					match parts.get(2) {
+						None => TaskmasterDaemonRequest::InfoTask(id),
+						Some(&"--tree") => TaskmasterDaemonRequest::InfoTaskTree(id),
+						_ => return Err("Expected --tree"),
+					}
+				},
 				"load" => TaskmasterDaemonRequest::LoadFile(resolve_path(parts[1])?),
 				"unload" => TaskmasterDaemonRequest::UnloadFile(resolve_path(parts[1])?),
+				"send" => {
+					let (id, process) = parse_selector(parts[1])?;
+					// The text is sent as typed, as one line
+					let text = line.split_once(parts[1]).map_or("", |(_, text)| text).trim_start();
+					TaskmasterDaemonRequest::SendTask{id, process, data: format!("{text}\n").into_bytes()}
+				},
+				"signal" => {
+					let (id, process) = parse_selector(parts.get(2).ok_or("Expected a task id after the signal")?)?;
+					TaskmasterDaemonRequest::SignalTask{id, process, signal: parts[1].to_owned()}
+				},
+				"scale" => TaskmasterDaemonRequest::ScaleTask{
+					program: parts[1].to_owned(),
+					numprocs: match parts.get(2) {
+						Some(&"reset") => None,
+						Some(n) => Some(n.parse::<usize>().map_err(|_| "Expected a number of processes or reset")?),
+						None => return Err("Expected a number of processes or reset"),
+					},
+				},
+				"logs" => {
+					let (id, process) = parse_selector(parts[1])?;
+					let stream = match parts.get(2) {
+						None | Some(&"stdout") => LogStream::Stdout,
+						Some(&"stderr") => LogStream::Stderr,
+						_ => return Err("Stream should be stdout or stderr"),
+					};
+					let lines = parts.get(3).map_or(Ok(10), |n| n.parse::<usize>()).map_err(|_| "Lines should be an int")?;
+					match process {
+						Some(process) => TaskmasterDaemonRequest::LogsProcess{id, process, stream, lines},
+						None => TaskmasterDaemonRequest::LogsTask{id, stream, lines},
+					}
+				},
 				_ => {
 					usage();
 					return Err("Invalid command");
@@ -146,8 +234,14 @@ fn parse_line(line: &str) -> Result<TaskmasterDaemonRequest, &str> {
 }
 
 fn main() {
-	let mut stream = UnixStream::connect("/tmp/taskmasterd.sock")
-		.expect("Could not connect to daemon");
+	// Requests like reload can take a while, wait as long as the daemon needs
+	let mut client = match TaskmasterClient::connect_timeout(DEFAULT_SOCKET_PATH, None) {
+		Ok(client) => client,
+		Err(err) => {
+			eprintln!("\x1b[91mError\x1b[0m: {err}");
+			return;
+		}
+	};
 
 	let helper = TaskmasterHelper {
 		highlighter: TaskmasterHighlighter::new(),
@@ -172,17 +266,74 @@ fn main() {
 					continue;
 				}
 
+				if matches!(line.trim(), "top" | "watch") {
+					match top::run(&mut client) {
+						Ok(()) => {}
+						Err(err @ (ClientError::Frame(_) | ClientError::Timeout | ClientError::Desynchronized)) => {
+							eprintln!("\x1b[91mError\x1b[0m: {err}");
+							break;
+						}
+						Err(err) => {
+							eprintln!("\x1b[91mError\x1b[0m: {err}");
+							rl.helper_mut().unwrap().status = Status::Error;
+						}
+					}
+					continue;
+				}
+
+				if line.split_whitespace().eq(["daemon", "upgrade"]) {
+					// The connection closes with the old binary, the new one gets a fresh one
+					let upgraded = client.upgrade()
+						.and_then(|_| TaskmasterClient::connect_timeout(DEFAULT_SOCKET_PATH, None));
+					match upgraded {
+						Ok(upgraded) => {
+							client = upgraded;
+							println!("\x1b[92mSuccess\x1b[0m");
+							rl.helper_mut().unwrap().status = Status::Success;
+						}
+						Err(err @ (ClientError::Frame(_) | ClientError::Timeout | ClientError::Desynchronized | ClientError::Connect(_))) => {
+							eprintln!("\x1b[91mError\x1b[0m: {err}");
+							break;
+						}
+						Err(err) => {
+							eprintln!("\x1b[91mError\x1b[0m: {err}");
+							rl.helper_mut().unwrap().status = Status::Error;
+						}
+					}
+					continue;
+				}
+
+				if let ["attach", selector] = line.split_whitespace().collect::<Vec<_>>()[..] {
+					let attached = parse_selector(selector)
+						.map_err(|err| err.to_owned())
+						.and_then(|(id, process)| attach::run(id, process.unwrap_or(0)).map_err(|err| err.to_string()));
+					if let Err(err) = attached {
+						eprintln!("\x1b[91mError\x1b[0m: {err}");
+						rl.helper_mut().unwrap().status = Status::Error;
+					}
+					continue;
+				}
+
 				match parse_line(line.as_str()) {
 					Ok(request) => {
-						bincode::serialize_into(&mut stream, &request).unwrap();
-						stream.flush().unwrap();
-
 						if let TaskmasterDaemonRequest::Stop = request {
+							if let Err(err) = client.shutdown() {
+								eprintln!("\x1b[91mError\x1b[0m: {err}");
+							}
 							println!("Stop!");
 							break;
 						}
 
-						match bincode::deserialize_from::<&UnixStream, TaskmasterDaemonResult>(&mut stream).unwrap() {
+						let result = match client.request(&request) {
+							Ok(result) => result,
+							Err(err @ (ClientError::Frame(_) | ClientError::Timeout | ClientError::Desynchronized)) => {
+								eprintln!("\x1b[91mError\x1b[0m: {err}");
+								break;
+							}
+							Err(err) => TaskmasterDaemonResult::Err(err.to_string()),
+						};
+
+						match result {
 							TaskmasterDaemonResult::Success => {
 								println!("\x1b[92mSuccess\x1b[0m");
 								rl.helper_mut().unwrap().status = Status::Success;
@@ -199,6 +350,24 @@ fn main() {
 								eprintln!("\x1b[91mError\x1b[0m: {err}");
 								rl.helper_mut().unwrap().status = Status::Error;
 							}
+							TaskmasterDaemonResult::Hello(version) => {
+								println!("Protocol version {version}");
+								rl.helper_mut().unwrap().status = Status::Success;
+							}
+							TaskmasterDaemonResult::Event(event) => {
+								println!("{event:?}");
+								rl.helper_mut().unwrap().status = Status::Success;
+							}
+							TaskmasterDaemonResult::Output{data, ..} => {
+								print!("{}", String::from_utf8_lossy(&data));
+								rl.helper_mut().unwrap().status = Status::Success;
+							}
+							TaskmasterDaemonResult::Snapshot(processes) => {
+								for line in top::table(&processes, None) {
+									println!("{line}\x1b[0m");
+								}
+								rl.helper_mut().unwrap().status = Status::Success;
+							}
 						}
 					}
 					Err(err) => {
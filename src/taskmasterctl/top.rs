@@ -0,0 +1,234 @@
+use std::{io::{self, IsTerminal, Read, Write}, time::Duration};
+
+use taskmastersocket::{TaskmasterClient, ClientError, ClientResult, LogStream, ProcessSnapshot, format_duration, format_size};
+
+const REFRESH: Duration = Duration::from_secs(1);
+
+// Raw mode and alternate screen, both undone on drop
+struct Screen {
+	original: libc::termios,
+}
+
+impl Screen {
+	fn enter() -> io::Result<Screen> {
+		let mut original: libc::termios = unsafe { std::mem::zeroed() };
+		if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+			return Err(io::Error::last_os_error());
+		}
+
+		// Keys are read one by one, ctrl-c is handled as a key so the terminal gets restored
+		let mut raw = original;
+		raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+		raw.c_cc[libc::VMIN] = 0;
+		raw.c_cc[libc::VTIME] = 0;
+		if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+			return Err(io::Error::last_os_error());
+		}
+
+		print!("\x1b[?1049h\x1b[?25l");
+		Ok(Screen { original })
+	}
+
+	fn size(&self) -> (usize, usize) {
+		let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+		if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_row == 0 {
+			return (24, 80);
+		}
+		(size.ws_row as usize, size.ws_col as usize)
+	}
+
+	fn draw(&self, lines: &[String]) {
+		let (rows, cols) = self.size();
+		let mut out = String::from("\x1b[H\x1b[2J");
+
+		for line in lines.iter().take(rows) {
+			out.push_str(&truncate(line, cols));
+			out.push_str("\x1b[0m\r\n");
+		}
+		print!("{}", out.trim_end_matches("\r\n"));
+		let _ = io::stdout().flush();
+	}
+}
+
+impl Drop for Screen {
+	fn drop(&mut self) {
+		print!("\x1b[?25h\x1b[?1049l");
+		let _ = io::stdout().flush();
+		unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+	}
+}
+
+// Cut to the terminal width without counting escape sequences
+fn truncate(line: &str, width: usize) -> String {
+	let mut out = String::new();
+	let (mut visible, mut escape) = (0, false);
+
+	for c in line.chars() {
+		if c == '\x1b' {
+			escape = true;
+		}
+		if !escape {
+			if visible == width {
+				continue;
+			}
+			visible += 1;
+		}
+		if escape && c == 'm' {
+			escape = false;
+		}
+		out.push(c);
+	}
+	out
+}
+
+enum Key {
+	Up,
+	Down,
+	Char(char),
+	Escape,
+}
+
+fn read_key(timeout: Duration) -> Option<Key> {
+	let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+	if unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as i32) } <= 0 {
+		return None;
+	}
+
+	let mut buf = [0u8; 8];
+	let len = io::stdin().read(&mut buf).ok()?;
+	match &buf[..len] {
+		[] => None,
+		[27, b'[', b'A', ..] => Some(Key::Up),
+		[27, b'[', b'B', ..] => Some(Key::Down),
+		[27, ..] => Some(Key::Escape),
+		[3, ..] => Some(Key::Char('q')), // ctrl-c
+		[c, ..] => Some(Key::Char(*c as char)),
+	}
+}
+
+fn state_color(state: &str) -> &'static str {
+	match state {
+		"RUNNING" | "STARTING" => "\x1b[92m",
+		"STOPPING" | "STOPPED" | "KILLED" => "\x1b[93m",
+		"EXITED" | "LAUNCH_FAILED" => "\x1b[91m",
+		_ => "\x1b[90m",
+	}
+}
+
+// One line per process, the selected one in reverse video
+pub fn table(processes: &[ProcessSnapshot], selected: Option<usize>) -> Vec<String> {
+	let mut lines = vec![format!(
+		"\x1b[1m{:>4}  {:<20} {:>3}  {:<13} {:>8} {:>8} {:>8} {:>7} {:>8}",
+		"ID", "PROGRAM", "#", "STATE", "PID", "UPTIME", "RESTARTS", "CPU", "MEM"
+	)];
+
+	for (i, process) in processes.iter().enumerate() {
+		let reverse = if selected == Some(i) { "\x1b[7m" } else { "" };
+		lines.push(format!(
+			"{reverse}{:>4}  {:<20} {:>3}  {}{:<13}\x1b[0m{reverse} {:>8} {:>8} {:>8} {:>7} {:>8}",
+			process.task_id,
+			process.program,
+			process.process,
+			state_color(&process.state),
+			process.state,
+			process.pid.map(|pid| pid.to_string()).unwrap_or("-".to_owned()),
+			process.uptime_sec.map(format_duration).unwrap_or("-".to_owned()),
+			process.restarts,
+			process.cpu_percent.map(|cpu| format!("{cpu:.1}%")).unwrap_or("-".to_owned()),
+			process.rss_bytes.map(format_size).unwrap_or("-".to_owned()),
+		));
+	}
+	lines
+}
+
+enum View {
+	Table,
+	Logs(LogStream),
+}
+
+// Daemon errors are shown in the footer, anything else ends the dashboard
+fn outcome(result: ClientResult<()>, done: String) -> ClientResult<String> {
+	match result {
+		Ok(()) => Ok(done),
+		Err(ClientError::Daemon(err)) => Ok(format!("\x1b[91m{err}")),
+		Err(err) => Err(err),
+	}
+}
+
+pub fn run(client: &mut TaskmasterClient) -> ClientResult<()> {
+	if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+		eprintln!("\x1b[91mError\x1b[0m: top needs a terminal");
+		return Ok(());
+	}
+	let screen = match Screen::enter() {
+		Ok(screen) => screen,
+		Err(err) => {
+			eprintln!("\x1b[91mError\x1b[0m: {err}");
+			return Ok(());
+		}
+	};
+
+	let mut selected = 0;
+	let mut view = View::Table;
+	let mut message = String::new();
+
+	loop {
+		let processes = client.snapshot()?;
+		selected = selected.min(processes.len().saturating_sub(1));
+		let current = processes.get(selected);
+		let (rows, _) = screen.size();
+
+		let mut lines = Vec::new();
+		match (&view, current) {
+			(View::Logs(stream), Some(process)) => {
+				let name = if *stream == LogStream::Stdout { "stdout" } else { "stderr" };
+				lines.push(format!("\x1b[1m{} [{}] {name}\x1b[0m  \x1b[90me: switch stream  q: back", process.program, process.process));
+				match client.logs_process(process.task_id, process.process, *stream, rows.saturating_sub(2)) {
+					Ok(logs) => lines.extend(logs.lines().map(|line| line.to_owned())),
+					Err(ClientError::Daemon(err)) => lines.push(format!("\x1b[91m{err}")),
+					Err(err) => return Err(err),
+				}
+			}
+			_ => {
+				view = View::Table;
+				let mut table = table(&processes, Some(selected));
+				// Keep the selected row on screen
+				let body = rows.saturating_sub(3).max(1);
+				let skip = (selected + 1).saturating_sub(body);
+				lines.push(table.remove(0));
+				lines.extend(table.into_iter().skip(skip).take(body));
+				lines.resize(rows.saturating_sub(1).max(lines.len()), String::new());
+				lines.push(format!("\x1b[90ms: start  t: stop  r: restart  l: logs  q: quit  \x1b[0m{message}"));
+			}
+		}
+		screen.draw(&lines);
+
+		let Some(key) = read_key(REFRESH) else { continue };
+		message.clear();
+		match (&view, key) {
+			(View::Logs(_), Key::Char('q') | Key::Escape | Key::Char('l')) => view = View::Table,
+			(View::Logs(stream), Key::Char('e')) => {
+				view = View::Logs(if *stream == LogStream::Stdout { LogStream::Stderr } else { LogStream::Stdout });
+			}
+			(View::Logs(_), _) => {}
+			(View::Table, Key::Char('q') | Key::Escape) => return Ok(()),
+			(View::Table, Key::Up | Key::Char('k')) => selected = selected.saturating_sub(1),
+			(View::Table, Key::Down | Key::Char('j')) => selected += 1,
+			(View::Table, Key::Char(action @ ('s' | 't' | 'r' | 'l'))) => {
+				let Some(process) = current else { continue };
+				let (id, index) = (process.task_id, process.process);
+				let name = format!("{}[{index}]", process.program);
+				message = match action {
+					's' => outcome(client.start_process(id, index), format!("Started {name}"))?,
+					't' => outcome(client.stop_process(id, index), format!("Stopped {name}"))?,
+					'r' => outcome(client.restart_process(id, index), format!("Restarted {name}"))?,
+					_ => {
+						view = View::Logs(LogStream::Stdout);
+						String::new()
+					}
+				};
+			}
+			(View::Table, _) => {}
+		}
+	}
+}
@@ -0,0 +1,228 @@
+use std::{ffi::CString, fs, io, os::unix::{ffi::OsStrExt, process::CommandExt}, path::{Path, PathBuf}, process::Command};
+
+use yaml_rust::Yaml;
+
+use crate::parse_size;
+
+const CGROUP2_SUPER_MAGIC: i64 = 0x63677270;
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct CgroupOptions {
+	parent: String,
+	memory_max: Option<u64>,
+	cpu_weight: Option<u64>,
+	cpu_max: Option<String>, // as written to cpu.max, "<quota> <period>" or "max <period>"
+	pids_max: Option<u64>,
+}
+
+impl CgroupOptions {
+	pub fn from_yaml(yaml: &Yaml) -> Result<CgroupOptions, String> {
+		let cpu_weight = yaml["cpu_weight"].as_i64().map(|weight| weight as u64);
+		if cpu_weight.is_some_and(|weight| !(1..=10000).contains(&weight)) {
+			return Err("cpu_weight need to be between 1 and 10000".to_owned());
+		}
+
+		// A percentage of one cpu, or the raw cpu.max value
+		let cpu_max = match yaml["cpu_max"].as_str() {
+			Some(percent) if percent.ends_with('%') => {
+				let percent = percent.trim_end_matches('%').trim().parse::<f64>().ok().filter(|p| *p > 0.0)
+					.ok_or("cpu_max need to be a positive percentage")?;
+				Some(format!("{} 100000", (percent * 1000.0) as u64))
+			}
+			Some(max) => {
+				let mut fields = max.split_whitespace();
+				let valid = matches!(fields.next(), Some(quota) if quota == "max" || quota.parse::<u64>().is_ok())
+					&& fields.next().is_none_or(|period| period.parse::<u64>().is_ok())
+					&& fields.next().is_none();
+				if !valid {
+					return Err("cpu_max need to be a percentage or \"<quota> <period>\"".to_owned());
+				}
+				Some(max.to_owned())
+			}
+			None => None,
+		};
+
+		let pids_max = match &yaml["pids_max"] {
+			Yaml::BadValue => None,
+			max => Some(max.as_i64().and_then(|max| u64::try_from(max).ok()).ok_or("pids_max need to be a positive number")?),
+		};
+
+		Ok(CgroupOptions {
+			parent: yaml["cgroup_parent"].as_str().unwrap_or("/sys/fs/cgroup/taskmaster").to_owned(),
+			memory_max: parse_size(&yaml["memory_max"])?,
+			cpu_weight,
+			cpu_max,
+			pids_max,
+		})
+	}
+
+	// Without any limit a missing cgroup is not worth reporting
+	pub fn has_limits(&self) -> bool {
+		self.memory_max.is_some() || self.cpu_weight.is_some() || self.cpu_max.is_some() || self.pids_max.is_some()
+	}
+}
+
+fn is_cgroup2(path: &Path) -> bool {
+	let Ok(path) = CString::new(path.as_os_str().as_bytes()) else { return false };
+	let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+	unsafe { libc::statfs(path.as_ptr(), &mut stat) == 0 && stat.f_type as i64 == CGROUP2_SUPER_MAGIC }
+}
+
+fn write(dir: &Path, file: &str, value: &str) -> Result<(), String> {
+	fs::write(dir.join(file), value).map_err(|err| format!("{}: {err}", dir.join(file).display()))
+}
+
+// Processes can only live in leaves once controllers are enabled, so each process
+// gets <parent>/<program>/<index> while the limits are set on <parent>/<program>
+pub struct Cgroup {
+	path: PathBuf,
+}
+
+impl Cgroup {
+	pub fn create(opts: &CgroupOptions, program: &str, index: usize) -> Result<Cgroup, String> {
+		let parent = Path::new(&opts.parent);
+		let existing = parent.ancestors().find(|dir| dir.exists()).unwrap_or(Path::new("/"));
+		if !is_cgroup2(existing) {
+			return Err(format!("{} is not on a cgroup v2 filesystem", opts.parent));
+		}
+
+		let group = parent.join(program);
+		let leaf = group.join(index.to_string());
+		fs::create_dir_all(&leaf).map_err(|err| format!("{}: {err}", leaf.display()))?;
+
+		let mut controllers = Vec::new();
+		if opts.memory_max.is_some() {
+			controllers.push("memory");
+		}
+		if opts.cpu_weight.is_some() || opts.cpu_max.is_some() {
+			controllers.push("cpu");
+		}
+		if opts.pids_max.is_some() {
+			controllers.push("pids");
+		}
+
+		// Controllers have to be enabled on every level down to the program
+		let mut levels: Vec<&Path> = group.ancestors().skip(1).take_while(|dir| is_cgroup2(dir)).collect();
+		levels.reverse();
+		for controller in controllers {
+			for dir in &levels {
+				// Fails on levels holding processes, whether it worked is checked below
+				let _ = write(dir, "cgroup.subtree_control", &format!("+{controller}"));
+			}
+			let available = fs::read_to_string(group.join("cgroup.controllers")).unwrap_or_default();
+			if !available.split_whitespace().any(|c| c == controller) {
+				return Err(format!("the {controller} controller is not available in {}", group.display()));
+			}
+		}
+
+		// Unset limits go back to their default, a reload may have removed them
+		let settings = [
+			("memory.max", opts.memory_max.map(|max| max.to_string()), "max"),
+			("cpu.weight", opts.cpu_weight.map(|weight| weight.to_string()), "100"),
+			("cpu.max", opts.cpu_max.clone(), "max"),
+			("pids.max", opts.pids_max.map(|max| max.to_string()), "max"),
+		];
+		for (file, value, default) in settings {
+			match value {
+				Some(value) => write(&group, file, &value)?,
+				None => { let _ = write(&group, file, default); }
+			}
+		}
+
+		Ok(Cgroup { path: leaf })
+	}
+
+	// The child moves itself before exec so it cannot fork outside of it
+	pub fn attach(&self, command: &mut Command) {
+		let procs = CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes()).unwrap();
+
+		unsafe {
+			command.pre_exec(move || {
+				let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+				if fd < 0 {
+					return Err(io::Error::last_os_error());
+				}
+				let written = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+				libc::close(fd);
+				if written != 1 {
+					return Err(io::Error::last_os_error());
+				}
+				Ok(())
+			});
+		}
+	}
+
+	// SIGKILL everything left in the cgroup, descendants included
+	pub fn kill(&self) {
+		if write(&self.path, "cgroup.kill", "1").is_ok() {
+			return;
+		}
+
+		// Before Linux 5.14 there is no cgroup.kill
+		let procs = fs::read_to_string(self.path.join("cgroup.procs")).unwrap_or_default();
+		for pid in procs.lines().filter_map(|pid| pid.parse::<i32>().ok()) {
+			unsafe { libc::kill(pid, libc::SIGKILL) };
+		}
+	}
+
+	pub fn memory_current(&self) -> Option<u64> {
+		fs::read_to_string(self.path.join("memory.current")).ok()?.trim().parse().ok()
+	}
+
+	pub fn pids_current(&self) -> Option<u64> {
+		let procs = fs::read_to_string(self.path.join("cgroup.procs")).ok()?;
+		Some(procs.lines().count() as u64)
+	}
+
+	pub fn contains(&self, pid: u32) -> bool {
+		let procs = fs::read_to_string(self.path.join("cgroup.procs")).unwrap_or_default();
+		procs.lines().any(|line| line.parse() == Ok(pid))
+	}
+
+	// Fails while something still runs in it
+	pub fn remove(&self) {
+		let _ = fs::remove_dir(&self.path);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use yaml_rust::YamlLoader;
+
+	use super::CgroupOptions;
+
+	fn options(text: &str) -> Result<CgroupOptions, String> {
+		CgroupOptions::from_yaml(&YamlLoader::load_from_str(text).unwrap().remove(0))
+	}
+
+	#[test]
+	fn limits_and_cpu_max_forms() {
+		let opts = options("memory_max: 256M\ncpu_weight: 200\npids_max: 64\ncgroup_parent: /sys/fs/cgroup/test").unwrap();
+		assert_eq!(opts.memory_max, Some(256 << 20));
+		assert_eq!(opts.cpu_weight, Some(200));
+		assert_eq!(opts.pids_max, Some(64));
+		assert_eq!(opts.parent, "/sys/fs/cgroup/test");
+		assert!(opts.has_limits());
+
+		assert_eq!(options("cpu_max: 50%").unwrap().cpu_max.as_deref(), Some("50000 100000"));
+		assert_eq!(options("cpu_max: 20000 50000").unwrap().cpu_max.as_deref(), Some("20000 50000"));
+		assert_eq!(options("cpu_max: max").unwrap().cpu_max.as_deref(), Some("max"));
+
+		let opts = options("{}").unwrap();
+		assert_eq!(opts.parent, "/sys/fs/cgroup/taskmaster");
+		assert!(!opts.has_limits());
+	}
+
+	#[test]
+	fn invalid_options_are_rejected() {
+		assert!(options("cpu_weight: 0").is_err());
+		assert!(options("cpu_weight: 10001").is_err());
+		assert!(options("cpu_max: 0%").is_err());
+		assert!(options("cpu_max: half%").is_err());
+		assert!(options("cpu_max: lots").is_err());
+		assert!(options("cpu_max: 1000 100000 1").is_err());
+		assert!(options("memory_max: 1X").is_err());
+		assert!(options("pids_max: -1").is_err());
+		assert!(options("pids_max: many").is_err());
+	}
+}
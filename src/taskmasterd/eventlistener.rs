@@ -0,0 +1,365 @@
+use std::{
+	collections::{HashMap, VecDeque},
+	io::{Read, Write, ErrorKind},
+	os::unix::io::AsRawFd,
+	process::{ChildStdin, ChildStdout},
+	sync::{atomic::{AtomicU64, Ordering}, mpsc::Receiver},
+};
+
+use taskmastersocket::TaskmasterEvent;
+
+use crate::events;
+
+// Speaks the supervisord eventlistener protocol: the listener writes READY,
+// gets a header line and a payload on stdin, and answers RESULT 2\nOK or RESULT 4\nFAIL
+// The processes of a program are a pool, each event goes to one of them that is READY
+
+static SERIAL: AtomicU64 = AtomicU64::new(0);
+
+type Event = (String, String); // name and payload
+
+#[derive(Debug, PartialEq)]
+enum ListenerState {
+	Acknowledged, // waiting for READY
+	Ready,
+	Busy(Event),  // event sent, waiting for its RESULT
+}
+
+struct Pipes {
+	stdin: ChildStdin,
+	stdout: ChildStdout,
+}
+
+// One process of the pool
+pub struct Listener {
+	state: ListenerState,
+	pipes: Option<Pipes>,
+	input: Vec<u8>,    // read from the listener, not parsed yet
+	output: Vec<u8>,   // to write to the listener
+	retry: Vec<Event>, // rejected, or in flight when the listener died
+}
+
+pub struct EventPool {
+	program: String,
+	filters: Vec<String>,
+	buffer_size: usize,
+	receiver: Receiver<TaskmasterEvent>,
+	buffer: VecDeque<Event>,
+	states: HashMap<(String, usize), &'static str>, // last state of each process, for from_state
+	pool_serial: u64,
+}
+
+fn set_nonblocking(fd: i32) {
+	unsafe {
+		let flags = libc::fcntl(fd, libc::F_GETFL);
+		libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+	}
+}
+
+fn state_name(event: &TaskmasterEvent) -> Option<&'static str> {
+	Some(match event {
+		TaskmasterEvent::Starting{..} => "STARTING",
+		TaskmasterEvent::Running{..} => "RUNNING",
+		TaskmasterEvent::Exited{..} => "EXITED",
+		TaskmasterEvent::Backoff{..} => "BACKOFF",
+		TaskmasterEvent::Fatal{..} => "FATAL",
+		TaskmasterEvent::Stopped{..} => "STOPPED",
+		_ => return None,
+	})
+}
+
+impl Listener {
+	pub fn new() -> Listener {
+		Listener {
+			state: ListenerState::Acknowledged,
+			pipes: None,
+			input: Vec::new(),
+			output: Vec::new(),
+			retry: Vec::new(),
+		}
+	}
+
+	pub fn attach(&mut self, stdin: ChildStdin, stdout: ChildStdout) {
+		set_nonblocking(stdin.as_raw_fd());
+		set_nonblocking(stdout.as_raw_fd());
+
+		self.detach();
+		self.pipes = Some(Pipes { stdin, stdout });
+	}
+
+	// An event that was in flight is sent again, to any listener of the pool
+	pub fn detach(&mut self) {
+		if let ListenerState::Busy(event) = std::mem::replace(&mut self.state, ListenerState::Acknowledged) {
+			self.retry.push(event);
+		}
+		self.pipes = None;
+		self.input.clear();
+		self.output.clear();
+	}
+
+	fn read(&mut self) {
+		let Some(pipes) = &mut self.pipes else { return };
+
+		let mut chunk = [0u8; 4096];
+		loop {
+			match pipes.stdout.read(&mut chunk) {
+				Ok(0) => break,
+				Ok(n) => self.input.extend_from_slice(&chunk[..n]),
+				Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+				Err(_) => break,
+			}
+		}
+	}
+
+	// Take the next line or RESULT block out of the input
+	fn parse(&mut self, program: &str) {
+		loop {
+			let Some(eol) = self.input.iter().position(|c| *c == b'\n') else { return };
+			let line = String::from_utf8_lossy(&self.input[..eol]).trim().to_owned();
+
+			match &self.state {
+				ListenerState::Busy(event) => {
+					let Some(len) = line.strip_prefix("RESULT ").and_then(|len| len.parse::<usize>().ok()) else {
+						eprintln!("{program}: unexpected output while busy: {line}");
+						self.input.drain(..=eol);
+						continue;
+					};
+					if self.input.len() < eol + 1 + len {
+						return;
+					}
+					let result: Vec<u8> = self.input.drain(..eol + 1 + len).skip(eol + 1).collect();
+					if result != b"OK" {
+						// Rejected events are retried first
+						self.retry.push(event.clone());
+					}
+					self.state = ListenerState::Acknowledged;
+				}
+				ListenerState::Acknowledged if line == "READY" => {
+					self.input.drain(..=eol);
+					self.state = ListenerState::Ready;
+				}
+				_ => {
+					eprintln!("{program}: unexpected output: {line}");
+					self.input.drain(..=eol);
+				}
+			}
+		}
+	}
+
+	fn send(&mut self, event: Event, program: &str, pool_serial: u64) {
+		let (name, payload) = &event;
+		let header = format!(
+			"ver:3.0 server:taskmaster serial:{} pool:{program} poolserial:{pool_serial} eventname:{name} len:{}\n",
+			SERIAL.fetch_add(1, Ordering::Relaxed) + 1,
+			payload.len()
+		);
+		self.output.extend_from_slice(header.as_bytes());
+		self.output.extend_from_slice(payload.as_bytes());
+		self.state = ListenerState::Busy(event);
+	}
+
+	fn flush(&mut self) {
+		let Some(pipes) = &mut self.pipes else { return };
+
+		while !self.output.is_empty() {
+			match pipes.stdin.write(&self.output) {
+				Ok(n) => { self.output.drain(..n); },
+				Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+				Err(_) => break,
+			}
+		}
+	}
+}
+
+impl EventPool {
+	// Events are buffered from now on, even while no listener is running
+	pub fn new(program: String, filters: Vec<String>, buffer_size: usize) -> EventPool {
+		EventPool {
+			program,
+			filters,
+			buffer_size,
+			receiver: events::listen(),
+			buffer: VecDeque::new(),
+			states: HashMap::new(),
+			pool_serial: 0,
+		}
+	}
+
+	// After a reload, what is buffered is kept
+	pub fn configure(&mut self, filters: Vec<String>, buffer_size: usize) {
+		self.filters = filters;
+		self.buffer_size = buffer_size;
+	}
+
+	pub fn buffered(&self) -> usize {
+		self.buffer.len()
+	}
+
+	fn wanted(&self, name: &str) -> bool {
+		self.filters.iter().any(|filter| name == filter || name.starts_with(&format!("{filter}_")))
+	}
+
+	fn serialize(&mut self, event: &TaskmasterEvent) -> Option<Event> {
+		let state = state_name(event)?;
+		let (program, process) = match event {
+			TaskmasterEvent::Starting{program, process}
+			| TaskmasterEvent::Running{program, process, ..}
+			| TaskmasterEvent::Exited{program, process, ..}
+			| TaskmasterEvent::Backoff{program, process, ..}
+			| TaskmasterEvent::Fatal{program, process, ..}
+			| TaskmasterEvent::Stopped{program, process} => (program.clone(), *process),
+			_ => return None,
+		};
+		let from_state = self.states.insert((program.clone(), process), state).unwrap_or("STOPPED");
+
+		// Our own state changes would keep the listeners busy forever
+		if program == self.program {
+			return None;
+		}
+
+		let mut payload = format!("processname:{program} groupname:{program} processnum:{process} from_state:{from_state}");
+		match event {
+			TaskmasterEvent::Running{pid, ..} => payload += &format!(" pid:{pid}"),
+			TaskmasterEvent::Exited{code, ..} => payload += &format!(" code:{code}"),
+			TaskmasterEvent::Backoff{retries, ..} => payload += &format!(" tries:{retries}"),
+			_ => {}
+		}
+
+		Some((format!("PROCESS_STATE_{state}"), payload))
+	}
+
+	// The oldest events are dropped when the buffer is full
+	fn push(&mut self, event: Event) {
+		if self.buffer.len() >= self.buffer_size {
+			if let Some((name, _)) = self.buffer.pop_front() {
+				eprintln!("{}: event buffer overflowed, discarding {name}", self.program);
+			}
+		}
+		self.buffer.push_back(event);
+	}
+
+	// Move pending events to the buffer
+	fn collect(&mut self) {
+		while let Ok(event) = self.receiver.try_recv() {
+			if let Some(event) = self.serialize(&event).filter(|(name, _)| self.wanted(name)) {
+				self.push(event);
+			}
+		}
+	}
+
+	// One event to each listener that is READY, the others wait in the buffer
+	fn dispatch(&mut self, listeners: &mut [&mut Listener]) {
+		for listener in listeners.iter_mut() {
+			for event in listener.retry.drain(..).rev() {
+				self.buffer.push_front(event);
+			}
+		}
+		for listener in listeners.iter_mut().filter(|listener| listener.state == ListenerState::Ready) {
+			let Some(event) = self.buffer.pop_front() else { break };
+			self.pool_serial += 1;
+			listener.send(event, &self.program, self.pool_serial);
+		}
+	}
+
+	pub fn poll<'a>(&mut self, listeners: impl Iterator<Item = &'a mut Listener>) {
+		self.collect();
+
+		let mut listeners: Vec<&mut Listener> = listeners.collect();
+		for listener in &mut listeners {
+			listener.read();
+			listener.parse(&self.program);
+		}
+		self.dispatch(&mut listeners);
+		for listener in &mut listeners {
+			listener.flush();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Event, EventPool, Listener, ListenerState};
+
+	fn event(name: &str) -> Event {
+		(name.to_owned(), format!("payload of {name}"))
+	}
+
+	fn listener(input: &str) -> Listener {
+		let mut listener = Listener::new();
+		listener.input.extend_from_slice(input.as_bytes());
+		listener.parse("listener");
+		listener
+	}
+
+	#[test]
+	fn ready_then_result() {
+		let mut ready = listener("READY\n");
+		assert_eq!(ready.state, ListenerState::Ready);
+
+		ready.send(event("PROCESS_STATE_RUNNING"), "listener", 1);
+		assert!(String::from_utf8_lossy(&ready.output).ends_with("eventname:PROCESS_STATE_RUNNING len:32\npayload of PROCESS_STATE_RUNNING"));
+
+		// The RESULT payload can arrive in pieces
+		ready.input.extend_from_slice(b"RESULT 2\nO");
+		ready.parse("listener");
+		assert!(matches!(ready.state, ListenerState::Busy(_)));
+		ready.input.extend_from_slice(b"KREADY\n");
+		ready.parse("listener");
+		assert_eq!(ready.state, ListenerState::Ready);
+		assert!(ready.retry.is_empty());
+	}
+
+	#[test]
+	fn rejected_and_lost_events_are_retried() {
+		let mut rejecting = listener("READY\n");
+		rejecting.send(event("PROCESS_STATE_EXITED"), "listener", 1);
+		rejecting.input.extend_from_slice(b"garbage\nRESULT 4\nFAIL");
+		rejecting.parse("listener");
+		assert_eq!(rejecting.state, ListenerState::Acknowledged);
+		assert_eq!(rejecting.retry, [event("PROCESS_STATE_EXITED")]);
+
+		let mut dying = listener("READY\n");
+		dying.send(event("PROCESS_STATE_FATAL"), "listener", 2);
+		dying.detach();
+		assert_eq!(dying.state, ListenerState::Acknowledged);
+		assert_eq!(dying.retry, [event("PROCESS_STATE_FATAL")]);
+	}
+
+	#[test]
+	fn unexpected_output_is_skipped() {
+		assert_eq!(listener("hello\nREADY\n").state, ListenerState::Ready);
+		assert_eq!(listener("RESULT 2\nOK").state, ListenerState::Acknowledged);
+	}
+
+	#[test]
+	fn buffer_drops_the_oldest_events() {
+		let mut pool = EventPool::new("listener".to_owned(), Vec::new(), 2);
+		for name in ["A", "B", "C"] {
+			pool.push(event(name));
+		}
+		assert_eq!(pool.buffer, [event("B"), event("C")]);
+	}
+
+	#[test]
+	fn each_event_goes_to_one_ready_listener() {
+		let mut pool = EventPool::new("listener".to_owned(), Vec::new(), 10);
+		pool.push(event("A"));
+		pool.push(event("B"));
+		pool.push(event("C"));
+
+		let (mut first, mut second, mut waiting) = (listener("READY\n"), listener("READY\n"), listener(""));
+		pool.dispatch(&mut [&mut first, &mut second, &mut waiting]);
+		assert_eq!(first.state, ListenerState::Busy(event("A")));
+		assert_eq!(second.state, ListenerState::Busy(event("B")));
+		assert_eq!(waiting.state, ListenerState::Acknowledged);
+		assert_eq!(pool.buffer, [event("C")]);
+
+		// A rejected event goes out again before the buffered ones
+		first.input.extend_from_slice(b"RESULT 4\nFAILREADY\n");
+		first.parse("listener");
+		pool.dispatch(&mut [&mut first, &mut second, &mut waiting]);
+		assert_eq!(first.state, ListenerState::Busy(event("A")));
+		assert_eq!(pool.buffer, [event("C")]);
+		assert_eq!(pool.pool_serial, 3);
+	}
+}
@@ -0,0 +1,48 @@
+use std::sync::{Mutex, mpsc::{self, Receiver, Sender, SyncSender}};
+
+use lazy_static::lazy_static;
+use taskmastersocket::TaskmasterEvent;
+
+enum EventSender {
+	Client(SyncSender<TaskmasterEvent>),
+	Daemon(Sender<TaskmasterEvent>), // drained by the health loop, bounded by its own buffer
+}
+
+struct Subscriber {
+	program: Option<String>,
+	sender: EventSender,
+}
+
+// Events a client can fall behind by before it is dropped
+const CLIENT_BACKLOG: usize = 1024;
+
+lazy_static! {
+	static ref SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+}
+
+// Events not tied to a program are sent to every subscriber
+pub fn subscribe(program: Option<String>) -> Receiver<TaskmasterEvent> {
+	let (sender, receiver) = mpsc::sync_channel(CLIENT_BACKLOG);
+
+	SUBSCRIBERS.lock().unwrap().push(Subscriber { program, sender: EventSender::Client(sender) });
+	receiver
+}
+
+// Every event, for the event listeners of the daemon
+pub fn listen() -> Receiver<TaskmasterEvent> {
+	let (sender, receiver) = mpsc::channel();
+
+	SUBSCRIBERS.lock().unwrap().push(Subscriber { program: None, sender: EventSender::Daemon(sender) });
+	receiver
+}
+
+pub fn emit(event: TaskmasterEvent) {
+	SUBSCRIBERS.lock().unwrap().retain(|subscriber| {
+		match (&subscriber.program, event.program(), &subscriber.sender) {
+			(Some(wanted), Some(program), _) if wanted != program => true,
+			// Drop the subscribers whose connection is gone, or too slow to keep up, emit never blocks
+			(_, _, EventSender::Client(sender)) => sender.try_send(event.clone()).is_ok(),
+			(_, _, EventSender::Daemon(sender)) => sender.send(event.clone()).is_ok(),
+		}
+	});
+}
@@ -0,0 +1,165 @@
+use std::{io, os::fd::{FromRawFd, OwnedFd, AsRawFd, IntoRawFd, RawFd}, process::Child, time::Duration};
+
+use lazy_static::lazy_static;
+
+use crate::procfs;
+
+// A process we spawned, or one left running by a previous daemon
+// Both are signaled through their pidfd, without one (before Linux 5.3) a recycled pid could be hit
+pub enum ProcessHandle {
+	Child{child: Child, pidfd: Option<OwnedFd>},
+	Adopted{pid: u32, starttime: u64, pidfd: Option<OwnedFd>},
+}
+
+lazy_static! {
+	// The pidfds of every tracked process, readable once it exited
+	static ref EXITS: Option<OwnedFd> = {
+		let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+		(fd >= 0).then(|| unsafe { OwnedFd::from_raw_fd(fd) })
+	};
+}
+
+// Notified once, closing the pidfd unregisters it
+fn watch(pidfd: &OwnedFd) {
+	let Some(epoll) = EXITS.as_ref() else { return };
+	let mut event = libc::epoll_event { events: (libc::EPOLLIN | libc::EPOLLONESHOT) as u32, u64: pidfd.as_raw_fd() as u64 };
+	unsafe { libc::epoll_ctl(epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, pidfd.as_raw_fd(), &mut event) };
+}
+
+// Sleep until a tracked process exits, or for at most timeout
+pub fn wait_for_exit(timeout: Duration) {
+	let Some(epoll) = EXITS.as_ref() else {
+		return std::thread::sleep(timeout);
+	};
+	let mut events: [libc::epoll_event; 16] = unsafe { std::mem::zeroed() };
+	unsafe { libc::epoll_wait(epoll.as_raw_fd(), events.as_mut_ptr(), events.len() as i32, timeout.as_millis() as i32) };
+}
+
+fn pidfd_open(pid: u32) -> io::Result<OwnedFd> {
+	let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+	if fd < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+// Same pid and same start time, so not a recycled pid
+fn is_alive(pid: u32, starttime: u64) -> bool {
+	procfs::stat(pid).is_ok_and(|stat| stat.starttime == starttime && stat.state != 'Z')
+}
+
+impl ProcessHandle {
+	// Until it is reaped the pid of a child cannot be recycled, opening its pidfd now is safe
+	pub fn spawned(child: Child) -> ProcessHandle {
+		let pidfd = pidfd_open(child.id()).ok();
+		pidfd.iter().for_each(watch);
+		ProcessHandle::Child{child, pidfd}
+	}
+
+	// None when the process is gone or the pid now belongs to another one
+	pub fn adopt(pid: u32, starttime: u64) -> Option<ProcessHandle> {
+		if !is_alive(pid, starttime) {
+			return None;
+		}
+		// Without pidfds the start time is checked on every poll
+		let pidfd = pidfd_open(pid).ok();
+		pidfd.iter().for_each(watch);
+		is_alive(pid, starttime).then_some(ProcessHandle::Adopted{pid, starttime, pidfd})
+	}
+
+	// Handed over by the daemon we were re-executed from, still our child so always tracked
+	pub fn inherited(pid: u32, starttime: u64, pidfd: OwnedFd) -> ProcessHandle {
+		watch(&pidfd);
+		ProcessHandle::Adopted{pid, starttime, pidfd: Some(pidfd)}
+	}
+
+	fn pidfd(&self) -> Option<&OwnedFd> {
+		match self {
+			ProcessHandle::Child{pidfd, ..} | ProcessHandle::Adopted{pidfd, ..} => pidfd.as_ref(),
+		}
+	}
+
+	// A pidfd left open across an exec of the daemon
+	pub fn inheritable_pidfd(&self) -> io::Result<RawFd> {
+		match self.pidfd() {
+			Some(pidfd) => Ok(pidfd.as_raw_fd()),
+			// Never closed, the exec comes right after
+			None => Ok(pidfd_open(self.id())?.into_raw_fd()),
+		}
+	}
+
+	pub fn id(&self) -> u32 {
+		match self {
+			ProcessHandle::Child{child, ..} => child.id(),
+			ProcessHandle::Adopted{pid, ..} => *pid,
+		}
+	}
+
+	// Some once exited, with the exit code when known
+	// Adopted processes are not our children, their exit code cannot be collected
+	pub fn try_wait(&mut self) -> io::Result<Option<Option<i32>>> {
+		match self {
+			ProcessHandle::Child{child, ..} => Ok(child.try_wait()?.map(|status| status.code())),
+			ProcessHandle::Adopted{pidfd: Some(pidfd), ..} => {
+				let mut poll = libc::pollfd { fd: pidfd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+				if unsafe { libc::poll(&mut poll, 1, 0) } < 0 {
+					return Err(io::Error::last_os_error());
+				}
+				if poll.revents & libc::POLLIN == 0 {
+					return Ok(None);
+				}
+
+				// Processes handed over by an exec are still our children and can be reaped
+				let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+				if unsafe { libc::waitid(libc::P_PIDFD, pidfd.as_raw_fd() as libc::id_t, &mut info, libc::WEXITED | libc::WNOHANG) } == 0
+					&& info.si_code == libc::CLD_EXITED {
+					return Ok(Some(Some(unsafe { info.si_status() })));
+				}
+				Ok(Some(None))
+			}
+			ProcessHandle::Adopted{pid, starttime, pidfd: None} => Ok((!is_alive(*pid, *starttime)).then_some(None)),
+		}
+	}
+
+	// Only blocks for our own children, others are reaped by their parent
+	pub fn wait(&mut self) -> io::Result<()> {
+		match self {
+			ProcessHandle::Child{child, ..} => child.wait().map(|_| ()),
+			ProcessHandle::Adopted{pidfd: Some(pidfd), ..} => {
+				let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+				if unsafe { libc::waitid(libc::P_PIDFD, pidfd.as_raw_fd() as libc::id_t, &mut info, libc::WEXITED) } < 0 {
+					let err = io::Error::last_os_error();
+					if err.raw_os_error() != Some(libc::ECHILD) {
+						return Err(err);
+					}
+				}
+				Ok(())
+			}
+			ProcessHandle::Adopted{pidfd: None, ..} => Ok(()),
+		}
+	}
+
+	pub fn signal(&self, signal: libc::c_int) -> io::Result<()> {
+		let ret = match (self.pidfd(), self) {
+			(Some(pidfd), _) => unsafe {
+				libc::syscall(libc::SYS_pidfd_send_signal, pidfd.as_raw_fd(), signal, std::ptr::null::<libc::siginfo_t>(), 0) as i32
+			},
+			// Not reaped yet, the pid is still ours
+			(None, ProcessHandle::Child{child, ..}) => unsafe { libc::kill(child.id() as i32, signal) },
+			(None, ProcessHandle::Adopted{pid, starttime, ..}) if is_alive(*pid, *starttime) => unsafe { libc::kill(*pid as i32, signal) },
+			(None, ProcessHandle::Adopted{..}) => return Err(io::Error::from_raw_os_error(libc::ESRCH)),
+		};
+		if ret < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+
+	// Already exited is not an error
+	pub fn kill(&mut self) -> io::Result<()> {
+		match self.signal(libc::SIGKILL) {
+			Err(err) if err.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+			result => result,
+		}
+	}
+}
@@ -0,0 +1,83 @@
+use std::{fs::{File, OpenOptions}, os::unix::process::CommandExt, process::{Child, Command, Stdio}, time::{Duration, Instant}};
+
+use crate::TaskOptions;
+
+// Log files are opened in append mode so hooks and the program can share them
+pub fn open_log(path: &str) -> Result<File, String> {
+	OpenOptions::new().create(true).append(true).open(path)
+		.map_err(|err| format!("Could not open {path}: {err}"))
+}
+
+// A shell command run around the lifecycle of a process
+pub struct Hook {
+	name: &'static str,
+	child: Child,
+	started: Instant,
+	timeout: Duration,
+}
+
+impl Hook {
+	// opts of the process instance, templates already expanded
+	pub fn start(name: &'static str, cmd: &str, opts: &TaskOptions, program: &str, index: usize, pid: Option<u32>) -> Result<Hook, String> {
+		let mut command = Command::new("/bin/sh");
+
+		command.arg("-c").arg(cmd);
+		// Killed as a whole, what the shell started is not left behind
+		command.process_group(0);
+		command.stdin(Stdio::null());
+		command.stdout(match &opts.stdout {
+			Some(path) => Stdio::from(open_log(path)?),
+			None => Stdio::null(),
+		});
+		command.stderr(match &opts.stderr {
+			Some(path) => Stdio::from(open_log(path)?),
+			None => Stdio::null(),
+		});
+		command.envs(&opts.env);
+		command.env("TASKMASTER_PROGRAM", program);
+		command.env("TASKMASTER_PROCESS", index.to_string());
+		if let Some(pid) = pid {
+			command.env("TASKMASTER_PID", pid.to_string());
+		}
+		if let Some(workingdir) = &opts.workingdir {
+			command.current_dir(workingdir);
+		}
+
+		let child = command.spawn().map_err(|err| format!("{name} could not be started: {err}"))?;
+
+		Ok(Hook {
+			name,
+			child,
+			started: Instant::now(),
+			timeout: Duration::from_secs(opts.hook_timeout_sec),
+		})
+	}
+
+	pub fn name(&self) -> &'static str {
+		self.name
+	}
+
+	pub fn id(&self) -> u32 {
+		self.child.id()
+	}
+
+	// None while the hook is still running, it is killed once it exceeds its timeout
+	pub fn poll(&mut self) -> Option<Result<(), String>> {
+		match self.child.try_wait() {
+			Ok(Some(status)) if status.success() => Some(Ok(())),
+			Ok(Some(status)) => Some(Err(format!("{} failed with {status}", self.name))),
+			Ok(None) if self.started.elapsed() >= self.timeout => {
+				self.kill();
+				Some(Err(format!("{} timed out after {}s", self.name, self.timeout.as_secs())))
+			}
+			Ok(None) => None,
+			Err(err) => Some(Err(format!("{} could not be waited: {err}", self.name))),
+		}
+	}
+
+	pub fn kill(&mut self) {
+		unsafe { libc::kill(-(self.child.id() as i32), libc::SIGKILL) };
+		let _ = self.child.kill();
+		let _ = self.child.wait();
+	}
+}
@@ -0,0 +1,136 @@
+use std::{fmt, io, os::unix::process::CommandExt, process::Command};
+
+use yaml_rust::Yaml;
+
+use crate::parse_size;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+const RESOURCES: [(&str, Resource); 7] = [
+	("nofile", libc::RLIMIT_NOFILE),
+	("nproc", libc::RLIMIT_NPROC),
+	("core", libc::RLIMIT_CORE),
+	("as", libc::RLIMIT_AS),
+	("cpu", libc::RLIMIT_CPU),
+	("memlock", libc::RLIMIT_MEMLOCK),
+	("stack", libc::RLIMIT_STACK),
+];
+
+#[derive(PartialEq, Clone)]
+pub struct Limit {
+	name: &'static str,
+	resource: Resource,
+	soft: libc::rlim_t,
+	hard: libc::rlim_t,
+}
+
+impl fmt::Debug for Limit {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let value = |v: libc::rlim_t| if v == libc::RLIM_INFINITY { "unlimited".to_owned() } else { v.to_string() };
+		write!(f, "{}={}/{}", self.name, value(self.soft), value(self.hard))
+	}
+}
+
+fn current(resource: Resource) -> libc::rlimit {
+	let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+	unsafe { libc::getrlimit(resource, &mut limit) };
+	limit
+}
+
+// A number, a size with a K, M or G suffix, or unlimited
+fn parse_value(value: &Yaml) -> Result<Option<libc::rlim_t>, String> {
+	if value.as_str() == Some("unlimited") {
+		return Ok(Some(libc::RLIM_INFINITY));
+	}
+	parse_size(value).map(|v| v.map(|v| v as libc::rlim_t)).map_err(|err| err.to_owned())
+}
+
+// The `limits` section, a value sets both the soft and hard limit, {soft, hard} sets them apart
+pub fn from_yaml(yaml: &Yaml) -> Result<Vec<Limit>, String> {
+	if yaml.is_badvalue() {
+		return Ok(Vec::new());
+	}
+	let limits = yaml.as_hash().ok_or("limits need to be a map")?;
+
+	let mut parsed = Vec::new();
+	for (key, value) in limits {
+		let name = key.as_str().unwrap_or_default();
+		let (name, resource) = *RESOURCES.iter().find(|(n, _)| *n == name)
+			.ok_or(format!("Unknown limit {name:?}, expected one of nofile, nproc, core, as, cpu, memlock or stack"))?;
+		let daemon = current(resource);
+
+		let err = |err: String| format!("limits.{name}: {err}");
+		let (soft, hard) = if value.as_hash().is_some() {
+			let hard = parse_value(&value["hard"]).map_err(err)?.unwrap_or(daemon.rlim_max);
+			(parse_value(&value["soft"]).map_err(err)?.unwrap_or(hard), hard)
+		} else {
+			let limit = parse_value(value).map_err(err)?.ok_or(format!("limits.{name} is empty"))?;
+			(limit, limit)
+		};
+
+		if soft > hard {
+			return Err(format!("limits.{name}: soft limit is above the hard limit"));
+		}
+		// Only root can raise a hard limit, the daemon runs as the invoking user
+		if hard > daemon.rlim_max && unsafe { libc::geteuid() } != 0 {
+			return Err(format!("limits.{name}: hard limit is above the daemon's own ({})", daemon.rlim_max));
+		}
+		parsed.push(Limit { name, resource, soft, hard });
+	}
+	Ok(parsed)
+}
+
+// Applied in the child, between fork and exec
+pub fn apply(command: &mut Command, limits: &[Limit]) {
+	if limits.is_empty() {
+		return;
+	}
+	let limits = limits.to_vec();
+
+	unsafe {
+		command.pre_exec(move || {
+			for limit in &limits {
+				let rlimit = libc::rlimit { rlim_cur: limit.soft, rlim_max: limit.hard };
+				if libc::setrlimit(limit.resource, &rlimit) != 0 {
+					return Err(io::Error::last_os_error());
+				}
+			}
+			Ok(())
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use yaml_rust::{Yaml, YamlLoader};
+
+	use super::from_yaml;
+
+	fn limits(text: &str) -> Result<Vec<String>, String> {
+		let yaml = YamlLoader::load_from_str(text).unwrap().remove(0);
+		from_yaml(&yaml).map(|limits| limits.iter().map(|limit| format!("{limit:?}")).collect())
+	}
+
+	#[test]
+	fn single_values_and_soft_hard_pairs() {
+		assert_eq!(from_yaml(&Yaml::BadValue), Ok(Vec::new()));
+		assert_eq!(limits("nofile: 1024"), Ok(vec!["nofile=1024/1024".to_owned()]));
+		assert_eq!(limits("core: 0\nstack: 8M"), Ok(vec!["core=0/0".to_owned(), format!("stack={}/{}", 8 << 20, 8 << 20)]));
+		assert_eq!(limits("nproc: {soft: 64, hard: 128}"), Ok(vec!["nproc=64/128".to_owned()]));
+		// Without soft, the soft limit is the hard one
+		assert_eq!(limits("nofile: {hard: 512}"), Ok(vec!["nofile=512/512".to_owned()]));
+	}
+
+	#[test]
+	fn invalid_limits_are_rejected() {
+		assert!(limits("[nofile]").is_err());
+		assert!(limits("files: 10").is_err());
+		assert!(limits("nofile: -1").is_err());
+		assert!(limits("nofile: lots").is_err());
+		assert!(limits("nofile:").is_err());
+		assert!(limits("nproc: {soft: 128, hard: 64}").is_err());
+	}
+}
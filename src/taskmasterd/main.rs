@@ -1,11 +1,43 @@
 extern crate taskmastersocket;
 use lazy_static::lazy_static;
-use taskmastersocket::{TaskmasterDaemonRequest, TaskmasterDaemonResult};
+use taskmastersocket::{TaskmasterDaemonRequest, TaskmasterDaemonResult, TaskmasterEvent, LogStream, ProcessSnapshot, FrameError, read_frame, write_frame, negotiate_version, parse_signal, format_size, PROTOCOL_VERSION};
 
-use std::{collections::{HashMap, HashSet}, process::Child, fs::File, os::unix::{net::{UnixListener, UnixStream}}, thread, io::Write, sync::{Mutex, Arc, MutexGuard}, time::{Duration, Instant}};
+use std::{collections::{HashMap, HashSet}, process::{ChildStdin, Stdio}, fs::File, os::unix::{net::{UnixListener, UnixStream}, process::CommandExt}, thread, io::{Read, Write, Seek, SeekFrom}, os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd}, sync::{mpsc, Mutex, Arc, MutexGuard, atomic::{AtomicI32, Ordering}}, time::{Duration, Instant}};
 
 use daemonize::Daemonize;
 
+mod events;
+mod eventlistener;
+use eventlistener::{EventPool, Listener};
+mod hooks;
+use hooks::Hook;
+mod probes;
+use probes::{Probe, ProbeOptions};
+mod procfs;
+mod limits;
+use limits::Limit;
+mod cgroup;
+use cgroup::{Cgroup, CgroupOptions};
+mod sched;
+use sched::SchedOptions;
+mod sandbox;
+use sandbox::SandboxOptions;
+mod watchdog;
+use watchdog::Watchdog;
+mod stats;
+use stats::Sampler;
+mod handle;
+use handle::ProcessHandle;
+mod state;
+mod orphans;
+mod sockets;
+use sockets::SocketOptions;
+mod template;
+mod output;
+use output::Output;
+use orphans::{Orphan, Reaper};
+use state::{SavedState, SavedFile, SavedProcess};
+
 macro_rules! get_required (
 	($yaml:ident, $key:tt, $convert:ident) => (
 		$yaml[$key].$convert()
@@ -27,10 +59,19 @@ enum TaskOptionAutoRestart {
 	Unexpected(HashSet<i32>)
 }
 
+#[derive(PartialEq, Clone, Debug)]
+enum TaskOptionStdin {
+	Null,
+	File(String),
+	Pipe, // written to with send or attach
+}
+
 #[derive(PartialEq, Clone, Debug)]
 struct TaskOptions {
 	argv: Vec<String>,
 	numprocs: u64,
+	numprocs_start: u64, // process_num of the first process
+	here: String,        // directory of the config file
 	autostart: bool,
 	autorestart: TaskOptionAutoRestart,
 	starttime_sec: u64,
@@ -39,17 +80,61 @@ struct TaskOptions {
 	stoptime_sec: u64,
 	stdout: Option<String>,
 	stderr: Option<String>,
+	stdin: TaskOptionStdin,
 	env: HashMap<String, String>,
 	workingdir: Option<String>,
 	umask: u16,
+	eventlistener: bool,
+	events: Vec<String>, // event names or prefixes sent to an eventlistener
+	buffer_size: usize,
+	pre_start: Option<String>,
+	post_start: Option<String>,
+	pre_stop: Option<String>,
+	post_stop: Option<String>,
+	hook_timeout_sec: u64,
+	readiness: Option<ProbeOptions>,
+	liveness: Option<ProbeOptions>,
+	max_rss: Option<u64>, // bytes
+	max_cpu_percent: Option<f64>,
+	cpu_window_sec: u64,
+	max_open_fds: Option<u64>,
+	limits: Vec<Limit>,
+	cgroup: CgroupOptions,
+	sched: SchedOptions,
+	sandbox: SandboxOptions,
+	sockets: SocketOptions,
+}
+
+impl TaskOptions {
+	// Templates of cmd, stdout, stderr, env and workingdir expanded for one process
+	fn instance(&self, program: &str, index: usize) -> Result<TaskOptions, String> {
+		let context = template::Context {
+			program_name: program,
+			process_num: self.numprocs_start + index as u64,
+			here: &self.here,
+		};
+		let expand = |template: &String| template::expand(template, &context);
+
+		Ok(TaskOptions {
+			argv: self.argv.iter().map(expand).collect::<Result<_, _>>()?,
+			stdout: self.stdout.as_ref().map(expand).transpose()?,
+			stderr: self.stderr.as_ref().map(expand).transpose()?,
+			env: self.env.iter().map(|(key, value)| Ok((key.clone(), expand(value)?))).collect::<Result<_, String>>()?,
+			workingdir: self.workingdir.as_ref().map(expand).transpose()?,
+			..self.clone()
+		})
+	}
 }
 
 enum ExitStatus {
 	NotRunning,
 	LaunchFailed{at: Instant, err: String},
 	
+	PreStart{at: Instant},
+	Starting{since: Instant, pid: u32},
 	Running{since: Instant, pid: u32},
 	
+	PreStop{at: Instant},
 	Stopping{at: Instant},
 	
 	Exited{at: Instant, code: i32},
@@ -58,47 +143,201 @@ enum ExitStatus {
 }
 
 struct Process {
-	process: Option<Child>,
+	program: String,
+	index: usize,
+	process: Option<ProcessHandle>,
+	starttime: u64, // of the process in clock ticks since boot, saved to adopt it again
+	stdin: Option<ChildStdin>,
+	output: Output, // of an attachable process
+	pgrp: Option<u32>,  // of the last process, kept after it exited for the orphans it left
+	orphans: Vec<u32>, // still running, re-parented to us
 	created_at: Instant,
 	retries_count: u64,
-	current_status: ExitStatus
+	spawns: u64, // never reset, restarts are all of them but the first
+	current_status: ExitStatus,
+	listener: Option<Listener>,
+	hook: Option<Hook>,          // pre_start or pre_stop, the process waits for it
+	kill_after_hook: bool,       // pre_stop of a forced stop, killed instead of signaled once done
+	background_hooks: Vec<Hook>, // post_start and post_stop
+	ready: bool,
+	not_ready: Option<String>, // a start failed its readiness probe since the last restart asked for
+	readiness: Option<Probe>,
+	liveness: Option<Probe>,
+	watchdog: Option<Watchdog>,
+	cgroup: Option<Cgroup>,
+	stats: Option<Sampler>,
+	instance: Option<TaskOptions>, // of the running process, templates expanded
+	cgroup_error: Option<String>, // only kept when the program asked for cgroup limits
+	restart_reason: Option<String>,          // restart in progress
+	last_restart: Option<(Instant, String)>, // last restart decided by the daemon
 }
 
 impl Process {
-	fn new() -> Process {
+	fn new(program: String, index: usize) -> Process {
 		Process {
+			program,
+			index,
 			process: None,
+			starttime: 0,
+			stdin: None,
+			output: Output::default(),
+			pgrp: None,
+			orphans: Vec::new(),
 			created_at: Instant::now(),
 			retries_count: 0,
-			current_status: ExitStatus::NotRunning
+			spawns: 0,
+			current_status: ExitStatus::NotRunning,
+			listener: None,
+			hook: None,
+			kill_after_hook: false,
+			background_hooks: Vec::new(),
+			ready: false,
+			not_ready: None,
+			readiness: None,
+			liveness: None,
+			watchdog: None,
+			cgroup: None,
+			stats: None,
+			instance: None,
+			cgroup_error: None,
+			restart_reason: None,
+			last_restart: None,
 		}
 	}
 
+	fn launch_failed(&mut self, err: String) {
+		events::emit(TaskmasterEvent::Fatal{program: self.program.clone(), process: self.index, err: err.clone()});
+		self.current_status = ExitStatus::LaunchFailed{at: Instant::now(), err};
+	}
+
 	fn spawn(&mut self, opts: &TaskOptions) {
+		self.spawns += 1;
+		let instance = match opts.instance(&self.program, self.index) {
+			Ok(instance) => instance,
+			Err(err) => return self.launch_failed(err),
+		};
+
+		// Logs are truncated once per launch, the pre_start output is kept
+		for path in [&instance.stdout, &instance.stderr].into_iter().flatten() {
+			if File::create(path).is_err() {
+				return self.launch_failed(format!("Could not create {path}"));
+			}
+		}
+		// Expanded once, the hooks and the launch of this spawn all use it
+		let instance = self.instance.insert(instance);
+
+		if let Some(cmd) = &opts.pre_start {
+			match Hook::start("pre_start", cmd, instance, &self.program, self.index, None) {
+				Ok(hook) => {
+					self.hook = Some(hook);
+					self.current_status = ExitStatus::PreStart{at: Instant::now()};
+				}
+				Err(err) => self.launch_failed(err),
+			}
+			return;
+		}
+
+		self.launch(opts);
+	}
+
+	fn launch(&mut self, opts: &TaskOptions) {
+		let instance = self.instance.clone().unwrap_or_else(|| opts.clone());
 		let mut _spawn = || -> Result<(), String> {
-			let mut process = std::process::Command::new(&opts.argv[0]);
+			let opts = &instance;
+			// Programs with sockets are started through a shim that hands them over, see sockets::exec_program
+			let mut process = if opts.sockets.is_empty() {
+				std::process::Command::new(&opts.argv[0])
+			} else {
+				let mut shim = std::process::Command::new(daemon_exe().map_err(|err| format!("Could not find taskmasterd: {err}"))?);
+				shim.arg(&opts.argv[0]);
+				shim
+			};
 
 			process.args(&opts.argv[1..]);
-
-			if let Some(stdout) = &opts.stdout {
-				process.stdout(File::create(stdout).map_err(|_| "Could not create stdout file")?);
-			}
-			if let Some(stderr) = &opts.stderr {
-				process.stderr(File::create(stderr).map_err(|_| "Could not create stderr file")?);
+			// Its own process group, what it leaves behind can be told apart
+			process.process_group(0);
+
+			let mut stdout = opts.stdout.as_deref().map(hooks::open_log).transpose()?;
+			let mut stderr = opts.stderr.as_deref().map(hooks::open_log).transpose()?;
+			// Attachable, the output goes through the daemon to reach the attached clients too
+			let attachable = opts.stdin == TaskOptionStdin::Pipe;
+			if attachable {
+				process.stdout(Stdio::piped());
+				process.stderr(Stdio::piped());
+			} else {
+				if let Some(stdout) = stdout.take() {
+					process.stdout(stdout);
+				}
+				if let Some(stderr) = stderr.take() {
+					process.stderr(stderr);
+				}
 			}
+			match &opts.stdin {
+				TaskOptionStdin::Null => process.stdin(Stdio::null()),
+				TaskOptionStdin::File(path) => process.stdin(File::open(path).map_err(|err| format!("Could not open {path}: {err}"))?),
+				TaskOptionStdin::Pipe => process.stdin(Stdio::piped()),
+			};
 			process.envs(&opts.env);
+			opts.sockets.attach(&mut process)?;
 			if let Some(workingdir) = &opts.workingdir {
 				process.current_dir(workingdir);
 			}
-			unsafe { libc::umask(opts.umask) };
+			unsafe { libc::umask(opts.umask.into()) };
+			limits::apply(&mut process, &opts.limits);
+			opts.sched.apply(&mut process);
+
+			self.cgroup = match Cgroup::create(&opts.cgroup, &self.program, self.index) {
+				Ok(cgroup) => {
+					cgroup.attach(&mut process);
+					self.cgroup_error = None;
+					Some(cgroup)
+				}
+				Err(err) => {
+					if opts.cgroup.has_limits() {
+						eprintln!("{}[{}]: running without a cgroup: {err}", self.program, self.index);
+						self.cgroup_error = Some(err);
+					}
+					None
+				}
+			};
+
+			// Last so the cgroup is joined before entering the namespaces
+			let sandbox = opts.sandbox.apply(&mut process, &self.program, opts.workingdir.as_deref())?;
+
+			if opts.eventlistener {
+				process.stdin(Stdio::piped());
+				process.stdout(Stdio::piped());
+			}
 
 			match process.spawn() {
-				Ok(child) => {
-					self.current_status = ExitStatus::Running{since: Instant::now(), pid: child.id()};
-					self.process = Some(child);
+				Ok(mut child) => {
+					if opts.eventlistener {
+						self.listener.get_or_insert_with(Listener::new).attach(child.stdin.take().unwrap(), child.stdout.take().unwrap());
+					}
+					if attachable {
+						self.output.set(LogStream::Stdout, child.stdout.take().map(OwnedFd::from), stdout);
+						self.output.set(LogStream::Stderr, child.stderr.take().map(OwnedFd::from), stderr);
+					}
+					// Writes never block the daemon, a full pipe is reported instead
+					self.stdin = child.stdin.take().inspect(|stdin| unsafe {
+						libc::fcntl(stdin.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK);
+					});
+					self.current_status = ExitStatus::Starting{since: Instant::now(), pid: child.id()};
+					self.stats = Some(Sampler::new(child.id()));
+					self.starttime = procfs::stat(child.id()).map_or(0, |stat| stat.starttime);
+					self.pgrp = Some(child.id());
+					self.process = Some(ProcessHandle::spawned(child));
+					self.ready = opts.readiness.is_none();
+					self.readiness = opts.readiness.as_ref().map(Probe::new);
+					self.liveness = None;
+					self.watchdog = None;
+					events::emit(TaskmasterEvent::Starting{program: self.program.clone(), process: self.index});
 				},
 				Err(e) => {
-					return Err(e.to_string());
+					return Err(match &sandbox {
+						Some(report) => report.explain(&e),
+						None => e.to_string(),
+					});
 				}
 			}
 
@@ -106,41 +345,248 @@ impl Process {
 		};
 
 		if let Err(err) = _spawn() {
-			self.current_status = ExitStatus::LaunchFailed{at: Instant::now(), err};
+			self.launch_failed(err);
 		}
 	}
 
 	fn start(&mut self, opts: &TaskOptions) {
-		if self.process.is_some() {
+		// Started again once a stop in progress is done
+		if self.kill_after_hook || matches!(self.current_status, ExitStatus::PreStop{..} | ExitStatus::Stopping{..}) {
+			self.restart_reason = Some("restart requested".to_owned());
+			return;
+		}
+		if self.process.is_some() || self.hook.is_some() {
 			return;
 		}
 
 		self.spawn(opts);
 
 		self.retries_count = 0;
+		self.not_ready = None;
 	}
 
-	fn graceful_stop(&mut self, stopsignal: libc::c_int) {
-		if let Some(child) = &mut self.process {
-			unsafe { libc::kill(child.id() as i32, stopsignal); }
+	fn run_background_hook(&mut self, name: &'static str, cmd: &Option<String>, opts: &TaskOptions, pid: Option<u32>) {
+		if let Some(cmd) = cmd {
+			match Hook::start(name, cmd, self.instance.as_ref().unwrap_or(opts), &self.program, self.index, pid) {
+				Ok(hook) => self.background_hooks.push(hook),
+				Err(err) => eprintln!("{}[{}]: {err}", self.program, self.index),
+			}
+		}
+	}
+
+	// A pending pre_start means the process was never launched
+	fn cancel_pre_start(&mut self) -> bool {
+		match self.hook.take() {
+			Some(mut hook) if hook.name() == "pre_start" => {
+				hook.kill();
+				self.current_status = ExitStatus::Stopped{at: Instant::now()};
+				events::emit(TaskmasterEvent::Stopped{program: self.program.clone(), process: self.index});
+				true
+			}
+			hook => {
+				self.hook = hook;
+				false
+			}
+		}
+	}
+
+	fn send_stopsignal(&mut self, stopsignal: libc::c_int) {
+		if let Some(child) = &self.process {
+			if let Err(err) = child.signal(stopsignal) {
+				eprintln!("{}[{}]: could not send the stop signal: {err}", self.program, self.index);
+			}
 			self.current_status = ExitStatus::Stopping{at: Instant::now()};
 		}
 	}
 
-	fn stop(&mut self) {
-		if let Some(child) = &mut self.process {
-			child.kill();
-			self.process = None;
-			self.current_status = ExitStatus::Killed{at: Instant::now()};
+	fn graceful_stop(&mut self, opts: &TaskOptions) {
+		self.restart_reason = None;
+		if self.cancel_pre_start() || self.hook.is_some() {
+			return;
+		}
+		let Some(child) = &self.process else { return };
+
+		if let Some(cmd) = &opts.pre_stop {
+			match Hook::start("pre_stop", cmd, self.instance.as_ref().unwrap_or(opts), &self.program, self.index, Some(child.id())) {
+				Ok(hook) => {
+					self.hook = Some(hook);
+					self.current_status = ExitStatus::PreStop{at: Instant::now()};
+					return;
+				}
+				Err(err) => eprintln!("{}[{}]: {err}", self.program, self.index),
+			}
+		}
+		self.send_stopsignal(opts.stopsignal);
+	}
+
+	// Kill right away, or once pre_stop is done, the hooks are polled like those of a graceful stop
+	fn stop(&mut self, opts: &TaskOptions) {
+		self.restart_reason = None;
+		if self.cancel_pre_start() {
+			return;
+		}
+
+		// Escalating a graceful stop, pre_stop already ran or is cut short
+		let signaled = matches!(self.current_status, ExitStatus::PreStop{..} | ExitStatus::Stopping{..});
+		if let Some(mut hook) = self.hook.take() {
+			hook.kill();
+		}
+		self.kill_after_hook = false;
+
+		let Some(child) = &self.process else { return };
+		if let (Some(cmd), false) = (&opts.pre_stop, signaled) {
+			match Hook::start("pre_stop", cmd, self.instance.as_ref().unwrap_or(opts), &self.program, self.index, Some(child.id())) {
+				Ok(hook) => {
+					self.hook = Some(hook);
+					self.kill_after_hook = true;
+					self.current_status = ExitStatus::PreStop{at: Instant::now()};
+					return;
+				}
+				Err(err) => eprintln!("{}[{}]: {err}", self.program, self.index),
+			}
+		}
+		self.kill(opts);
+	}
+
+	fn kill(&mut self, opts: &TaskOptions) {
+		let Some(child) = &mut self.process else { return };
+
+		if let Some(cgroup) = self.cgroup.take() {
+			cgroup.kill();
+		}
+		// Reaped right away, a zombie left behind would be taken for an orphan
+		let _ = child.kill();
+		let _ = child.wait();
+		self.process = None;
+		self.stdin = None;
+		self.stats = None;
+		if let Some(listener) = &mut self.listener {
+			listener.detach();
+		}
+		self.current_status = ExitStatus::Killed{at: Instant::now()};
+		events::emit(TaskmasterEvent::Stopped{program: self.program.clone(), process: self.index});
+
+		self.run_background_hook("post_stop", &opts.post_stop, opts, None);
+		if self.restart_reason.take().is_some() {
+			self.spawn(opts);
+		}
+	}
+
+	// The process and its hooks, all waited for by us
+	fn pids(&self) -> impl Iterator<Item = u32> + '_ {
+		self.process.iter().map(ProcessHandle::id)
+			.chain(self.hook.iter().chain(&self.background_hooks).map(Hook::id))
+	}
+
+	// What an upgrade cannot hand over, hooks, stops and probe checks are waited for instead
+	fn in_flight(&self) -> Option<String> {
+		let name = format!("{}[{}]", self.program, self.index);
+		if let Some(hook) = self.hook.iter().chain(&self.background_hooks).next() {
+			return Some(format!("the {} hook of {name}", hook.name()));
+		}
+		if matches!(self.current_status, ExitStatus::Stopping{..}) {
+			return Some(format!("{name} to stop"));
+		}
+		if self.readiness.iter().chain(&self.liveness).any(Probe::checking) {
+			return Some(format!("a probe of {name}"));
+		}
+		None
+	}
+
+	// Running, or still running one of its hooks
+	fn busy(&self) -> bool {
+		self.process.is_some() || self.hook.is_some() || !self.background_hooks.is_empty()
+	}
+
+	// Never became ready, a failed start like an early exit: retried, then given up on
+	fn not_ready(&mut self, opts: &TaskOptions, reason: String) {
+		if self.retries_count >= opts.retries {
+			events::emit(TaskmasterEvent::Fatal{
+				program: self.program.clone(),
+				process: self.index,
+				err: format!("Gave up after {} retries, {reason}", self.retries_count)
+			});
+			self.graceful_stop(opts);
+		} else {
+			self.retries_count += 1;
+			events::emit(TaskmasterEvent::Backoff{program: self.program.clone(), process: self.index, retries: self.retries_count});
+			self.restart(opts, reason.clone());
+		}
+		self.not_ready = Some(reason);
+	}
+
+	// Restart through the normal stop path, pre_stop and stoptime included
+	fn restart(&mut self, opts: &TaskOptions, reason: String) {
+		events::emit(TaskmasterEvent::Restarting{program: self.program.clone(), process: self.index, reason: reason.clone()});
+		self.graceful_stop(opts);
+		self.not_ready = None;
+		self.last_restart = Some((Instant::now(), reason.clone()));
+		self.restart_reason = Some(reason);
+	}
+
+	// Where a process restarted by a rolling restart is, previous being the pid it had before
+	// None while on its way, the stop of the old process included
+	fn rolled(&self, previous: Option<u32>, opts: &TaskOptions, wait_ready: bool) -> Option<Result<(), String>> {
+		match self.current_status {
+			ExitStatus::Running{pid, ..} if Some(pid) != previous => Some(Ok(())),
+			// Retried or given up on by the health check
+			_ if wait_ready && self.not_ready.is_some() => self.not_ready.as_ref().map(|reason| Err(format!("{}[{}] {reason}", self.program, self.index))),
+			ExitStatus::Starting{since, pid} if Some(pid) != previous && !wait_ready && since.elapsed().as_secs() >= opts.starttime_sec => Some(Ok(())),
+			ExitStatus::Running{..} | ExitStatus::Starting{..} | ExitStatus::PreStart{..} | ExitStatus::PreStop{..} | ExitStatus::Stopping{..} => None,
+			ExitStatus::Exited{code, ..} => Some(Err(format!("{}[{}] exited with code {code}", self.program, self.index))),
+			ExitStatus::LaunchFailed{ref err, ..} => Some(Err(format!("{}[{}] could not start: {err}", self.program, self.index))),
+			_ => Some(Err(format!("{}[{}] stopped", self.program, self.index))),
+		}
+	}
+
+	fn poll_hooks(&mut self, opts: &TaskOptions) {
+		let (program, index) = (&self.program, self.index);
+		self.background_hooks.retain_mut(|hook| match hook.poll() {
+			None => true,
+			Some(Ok(())) => false,
+			Some(Err(err)) => {
+				eprintln!("{program}[{index}]: {err}");
+				false
+			}
+		});
+
+		let Some(result) = self.hook.as_mut().and_then(Hook::poll) else { return };
+		let hook = self.hook.take().unwrap();
+
+		match (hook.name(), result) {
+			("pre_start", Ok(())) => self.launch(opts),
+			("pre_start", Err(err)) => self.launch_failed(err),
+			(_, result) => {
+				if let Err(err) = result {
+					eprintln!("{}[{}]: {err}", self.program, self.index);
+				}
+				if std::mem::take(&mut self.kill_after_hook) {
+					self.kill(opts);
+				} else {
+					self.send_stopsignal(opts.stopsignal);
+				}
+			}
 		}
 	}
 
 	fn health_check(&mut self, opts: &TaskOptions) {
+		self.output.poll();
+		self.poll_hooks(opts);
+		if self.hook.is_some() {
+			return;
+		}
+
+		if let Some(stats) = &mut self.stats {
+			stats.sample();
+		}
+
 		if let Some(child) = &mut self.process {
-			if let Ok(Some(status)) = child.try_wait() {
+			if let Ok(Some(code)) = child.try_wait() {
+				// A process that was asked to stop is not restarted
+				let stopping = matches!(self.current_status, ExitStatus::Stopping{..});
 				let mut restart = opts.autorestart == TaskOptionAutoRestart::Always;
 
-				if let Some(code) = status.code() {
+				if let Some(code) = code {
 					self.current_status = ExitStatus::Exited{at: Instant::now(), code};
 					if let TaskOptionAutoRestart::Unexpected(codes) = &opts.autorestart {
 						restart = !codes.contains(&code);
@@ -150,52 +596,260 @@ impl Process {
 				}
 
 				self.process = None;
+				self.stdin = None;
+				self.stats = None;
+				if let Some(listener) = &mut self.listener {
+					listener.detach();
+				}
+				if let Some(cgroup) = self.cgroup.take() {
+					// Nothing started by a stopped process outlives it
+					if stopping {
+						cgroup.kill();
+					}
+					cgroup.remove();
+				}
+
+				if stopping {
+					events::emit(TaskmasterEvent::Stopped{program: self.program.clone(), process: self.index});
+					self.run_background_hook("post_stop", &opts.post_stop, opts, None);
+					if self.restart_reason.take().is_some() {
+						self.spawn(opts);
+					}
+					return;
+				}
+				events::emit(match code {
+					Some(code) => TaskmasterEvent::Exited{program: self.program.clone(), process: self.index, code},
+					None => TaskmasterEvent::Stopped{program: self.program.clone(), process: self.index},
+				});
 
-				if !restart || self.retries_count >= opts.retries {
+				if !restart {
+					return;
+				}
+				if self.retries_count >= opts.retries {
+					events::emit(TaskmasterEvent::Fatal{
+						program: self.program.clone(),
+						process: self.index,
+						err: format!("Gave up after {} retries", self.retries_count)
+					});
 					return;
 				}
 				self.retries_count += 1;
+				events::emit(TaskmasterEvent::Backoff{program: self.program.clone(), process: self.index, retries: self.retries_count});
 				self.spawn(opts);
+			} else if let ExitStatus::Starting{since, pid} = self.current_status {
+				if let Some(probe) = &mut self.readiness {
+					match probe.poll(self.instance.as_ref().unwrap_or(opts)) {
+						Some(Ok(())) => {
+							self.ready = true;
+							self.readiness = None;
+						}
+						Some(Err(err)) if probe.failures >= opts.readiness.as_ref().map_or(1, ProbeOptions::failure_threshold) => {
+							let reason = format!("readiness probe failed {} times: {err}", probe.failures);
+							self.not_ready(opts, reason);
+							return;
+						}
+						_ => {}
+					}
+				}
+				if self.ready && since.elapsed().as_secs() >= opts.starttime_sec {
+					self.liveness = opts.liveness.as_ref().map(Probe::new);
+					self.watchdog = Some(Watchdog::new());
+					self.current_status = ExitStatus::Running{since, pid};
+					events::emit(TaskmasterEvent::Running{program: self.program.clone(), process: self.index, pid});
+					self.run_background_hook("post_start", &opts.post_start, opts, Some(pid));
+				}
+			} else if let ExitStatus::Running{..} = self.current_status {
+				if let Some(probe) = &mut self.liveness {
+					if let Some(Err(err)) = probe.poll(self.instance.as_ref().unwrap_or(opts)) {
+						if probe.failures >= opts.liveness.as_ref().map_or(1, ProbeOptions::failure_threshold) {
+							self.restart(opts, format!("liveness probe failed: {err}"));
+							return;
+						}
+					}
+				}
+				let stats = self.stats.as_ref().and_then(|stats| stats.current.as_ref());
+				if let Some(reason) = self.watchdog.as_mut().zip(stats).and_then(|(watchdog, stats)| watchdog.check(stats, opts)) {
+					self.restart(opts, reason);
+				}
 			} else if let ExitStatus::Stopping { at } = &self.current_status {
 				if at.elapsed().as_secs() >= opts.stoptime_sec {
-					self.stop();
+					self.kill(opts);
 				}
 			}
 		}
 	}
 
-	fn status(&self, opts: &TaskOptions) -> String {
+	// Take back a process left running by a previous daemon
+	fn adopt(&mut self, opts: &TaskOptions, saved: &SavedProcess) {
+		self.retries_count = saved.retries;
+		// Handed over by an upgrade, closed again if the process is not taken back
+		let stdin = saved.stdin.map(|fd| ChildStdin::from(unsafe { OwnedFd::from_raw_fd(fd) }));
+		let stdout = saved.stdout.map(|fd| unsafe { OwnedFd::from_raw_fd(fd) });
+		let stderr = saved.stderr.map(|fd| unsafe { OwnedFd::from_raw_fd(fd) });
+		let handle = match saved.pidfd {
+			Some(pidfd) => saved.pid.map(|pid| ProcessHandle::inherited(pid, saved.starttime, unsafe { OwnedFd::from_raw_fd(pidfd) })),
+			None => saved.pid.and_then(|pid| ProcessHandle::adopt(pid, saved.starttime)),
+		};
+		let Some(mut handle) = handle else { return };
+
+		// Its pipes died with the previous daemon, a new one is started instead
+		if opts.eventlistener {
+			let _ = handle.kill();
+			let _ = handle.wait();
+			return;
+		}
+
+		let pid = handle.id();
+		let age = procfs::uptime().unwrap_or(0.0) - saved.starttime as f64 / procfs::clock_ticks() as f64;
+		let since = Instant::now().checked_sub(Duration::from_secs_f64(age.max(0.0))).unwrap_or(Instant::now());
+
+		self.cgroup = Cgroup::create(&opts.cgroup, &self.program, self.index).ok();
+		self.current_status = ExitStatus::Running{since, pid};
+		self.spawns = 1;
+		self.stats = Some(Sampler::new(pid));
+		self.starttime = saved.starttime;
+		self.pgrp = Some(pid);
+		self.instance = opts.instance(&self.program, self.index).ok();
+		self.process = Some(handle);
+		self.stdin = stdin;
+		// Log files are reopened for appending, they were only truncated by the launch
+		let instance = self.instance.as_ref().unwrap_or(opts);
+		let log = |path: &Option<String>| path.as_deref().and_then(|path| hooks::open_log(path).ok());
+		self.output.set(LogStream::Stdout, stdout, log(&instance.stdout));
+		self.output.set(LogStream::Stderr, stderr, log(&instance.stderr));
+		self.ready = true;
+		self.liveness = opts.liveness.as_ref().map(Probe::new);
+		self.watchdog = Some(Watchdog::new());
+		println!("{}[{}]: adopted pid {pid}", self.program, self.index);
+	}
+
+	fn saved(&self) -> SavedProcess {
+		SavedProcess {
+			program: self.program.clone(),
+			index: self.index,
+			pid: self.process.as_ref().map(ProcessHandle::id),
+			starttime: self.starttime,
+			retries: self.retries_count,
+			pidfd: None,
+			stdin: None,
+			stdout: None,
+			stderr: None,
+		}
+	}
+
+	// Also the fds a re-executed daemon needs to keep the process
+	fn handed_over(&self) -> Result<SavedProcess, String> {
+		let mut saved = self.saved();
+		if let Some(process) = &self.process {
+			saved.pidfd = Some(process.inheritable_pidfd().map_err(|err| format!("No pidfd for {}[{}]: {err}", self.program, self.index))?);
+		}
+		saved.stdin = self.stdin.as_ref().map(AsRawFd::as_raw_fd);
+		saved.stdout = self.output.fd(LogStream::Stdout);
+		saved.stderr = self.output.fd(LogStream::Stderr);
+		Ok(saved)
+	}
+
+	// Left behind by this process, by process group or by cgroup
+	fn owns(&self, orphan: &Orphan) -> bool {
+		self.pgrp == Some(orphan.pgrp) || self.cgroup.as_ref().is_some_and(|cgroup| cgroup.contains(orphan.pid))
+	}
+
+	fn write_stdin(&mut self, data: &[u8], opts: &TaskOptions) -> Result<(), String> {
+		let Some(stdin) = &mut self.stdin else {
+			return Err(match opts.stdin {
+				TaskOptionStdin::Pipe => format!("{}[{}] is not running", self.program, self.index),
+				_ => format!("The stdin of {} is not a pipe", self.program),
+			});
+		};
+		stdin.write_all(data).map_err(|err| match err.kind() {
+			std::io::ErrorKind::WouldBlock => format!("{}[{}] is not reading its stdin", self.program, self.index),
+			_ => format!("{}[{}]: {err}", self.program, self.index),
+		})
+	}
+
+	fn snapshot(&self, task_id: usize) -> ProcessSnapshot {
+		let (state, since) = match &self.current_status {
+			ExitStatus::NotRunning => ("NOT_RUNNING", None),
+			ExitStatus::LaunchFailed{..} => ("LAUNCH_FAILED", None),
+			ExitStatus::PreStart{..} => ("STARTING", None),
+			ExitStatus::Starting{since, ..} => ("STARTING", Some(since)),
+			ExitStatus::Running{since, ..} => ("RUNNING", Some(since)),
+			ExitStatus::PreStop{..} | ExitStatus::Stopping{..} => ("STOPPING", None),
+			ExitStatus::Exited{..} => ("EXITED", None),
+			ExitStatus::Stopped{..} => ("STOPPED", None),
+			ExitStatus::Killed{..} => ("KILLED", None),
+		};
+		let stats = self.stats.as_ref().and_then(|stats| stats.current.as_ref());
+
+		ProcessSnapshot {
+			task_id,
+			program: self.program.clone(),
+			process: self.index,
+			state: state.to_owned(),
+			pid: self.process.as_ref().map(ProcessHandle::id),
+			uptime_sec: since.map(|since| since.elapsed().as_secs()),
+			restarts: self.spawns.saturating_sub(1),
+			cpu_percent: stats.map(|stats| stats.cpu_percent),
+			rss_bytes: stats.map(|stats| stats.rss_bytes),
+		}
+	}
+
+	fn status(&self) -> String {
 		(match &self.current_status {
-			ExitStatus::NotRunning => format!("\x1b[90mNot running"),
+			ExitStatus::NotRunning => "\x1b[90mNot running".to_owned(),
 			ExitStatus::LaunchFailed{at, err} => format!("\x1b[91mLaunch failed ({}s ago): {err}", at.elapsed().as_secs()),
-			ExitStatus::Running{since, pid} => {
-				let since = since.elapsed().as_secs();
-				format!("\x1b[92m{} (started {}s ago with pid {pid})",
-					if since >= opts.starttime_sec { "Running" } else { "Starting..." },
-					since)
-			},
+			ExitStatus::PreStart{at} => format!("\x1b[92mStarting... (running pre_start for {}s)", at.elapsed().as_secs()),
+			ExitStatus::PreStop{at} => format!("\x1b[93mStopping... (running pre_stop for {}s)", at.elapsed().as_secs()),
+			ExitStatus::Starting{since, pid} => format!("\x1b[92mStarting... (started {}s ago with pid {pid})", since.elapsed().as_secs()),
+			ExitStatus::Running{since, pid} => format!("\x1b[92mRunning (started {}s ago with pid {pid})", since.elapsed().as_secs()),
 			ExitStatus::Stopping { at } => format!("\x1b[93mStopping... ({}s ago)", at.elapsed().as_secs()),
 			ExitStatus::Exited{at, code} => format!("\x1b[91mExited ({}s ago) with code {code}", at.elapsed().as_secs()),
 			ExitStatus::Stopped{at} => format!("\x1b[93mStopped ({}s ago)", at.elapsed().as_secs()),
 			ExitStatus::Killed{at} => format!("\x1b[93mKilled ({}s ago)", at.elapsed().as_secs()),
-		}) + &format!("\x1b[90m (created {}s ago, {} retries)\x1b[0m", self.created_at.elapsed().as_secs(), self.retries_count)
+		}) + &self.stats.as_ref().and_then(|stats| stats.current.as_ref())
+			.map(|stats| format!(" [{stats}]")).unwrap_or_default()
+		+ &format!("\x1b[90m (created {}s ago, {} retries{}{}{})\x1b[0m",
+			self.created_at.elapsed().as_secs(),
+			self.retries_count,
+			match (&self.cgroup, &self.cgroup_error) {
+				(Some(cgroup), _) => format!(", cgroup: {} pids{}",
+					cgroup.pids_current().unwrap_or(0),
+					cgroup.memory_current().map(|bytes| format!(", {}", format_size(bytes))).unwrap_or_default()),
+				(None, Some(err)) => format!(", no cgroup: {err}"),
+				(None, None) => String::new(),
+			},
+			[("readiness", &self.readiness), ("liveness", &self.liveness)].iter()
+				.filter_map(|(name, probe)| probe.as_ref()?.last_error.as_ref().map(|err| format!(", {name}: {err}")))
+				.collect::<String>(),
+			self.last_restart.as_ref().map(|(at, reason)| format!(", restarted {}s ago: {reason}", at.elapsed().as_secs())).unwrap_or_default())
 	}
 }
 
 struct Task {
 	id: usize,
+	name: String,
 	options: TaskOptions,
 	processes: Vec<Process>,
+	scaled_from: Option<u64>, // numprocs of the config while overridden by scale
+	retiring: Vec<Process>,   // removed by scale, stopping
+	events: Option<EventPool>, // of an eventlistener, shared by its processes
+	started: bool,            // started and not stopped since, by autostart or by hand
 }
 
 impl Task {
-	fn new(options: TaskOptions) -> Task {
-		static mut id: usize = 0;
+	fn new(name: String, options: TaskOptions) -> Task {
+		static mut ID: usize = 0;
 
+		let events = options.eventlistener.then(|| EventPool::new(name.clone(), options.events.clone(), options.buffer_size));
 		Task {
-			id: unsafe { id += 1; id },
+			id: unsafe { ID += 1; ID },
+			name,
 			options,
-			processes: Vec::new()
+			processes: Vec::new(),
+			scaled_from: None,
+			retiring: Vec::new(),
+			events,
+			started: false,
 		}
 	}
 
@@ -205,53 +859,193 @@ impl Task {
 		}
 	}
 
-	fn start(&mut self) {
+	// Up to numprocs, an index still held by a scaled down process gets that process back
+	// so that its cgroup and logs are never shared with a new one
+	fn grow(&mut self) {
 		while self.processes.len() < self.options.numprocs as usize {
-			self.processes.push(Process::new());
+			let index = self.processes.len();
+			let process = match self.retiring.iter().position(|process| process.index == index) {
+				Some(position) => self.retiring.remove(position),
+				None => Process::new(self.name.clone(), index),
+			};
+			self.processes.push(process);
 		}
+	}
+
+	fn start(&mut self) {
+		self.grow();
+		self.started = true;
 
 		for process in &mut self.processes {
 			process.start(&self.options);
 		}
 	}
 
+	// Adopt what is still running, then start as usual
+	fn restore(&mut self, saved: &[SavedProcess]) {
+		self.grow();
+		for process in &mut self.processes {
+			if let Some(saved) = saved.iter().find(|saved| saved.program == process.program && saved.index == process.index) {
+				process.adopt(&self.options, saved);
+			}
+		}
+		self.started = self.processes.iter().any(|process| process.process.is_some());
+		self.init();
+	}
+
 	fn graceful_stop(&mut self) {
+		self.started = false;
 		for process in &mut self.processes {
-			process.graceful_stop(self.options.stopsignal);
+			process.graceful_stop(&self.options);
 		}
 	}
 
 	fn stop(&mut self) {
-		for process in &mut self.processes {
-			process.stop();
+		self.started = false;
+		for process in self.processes.iter_mut().chain(&mut self.retiring) {
+			process.stop(&self.options);
 		}
+		self.retiring.retain(Process::busy);
+	}
+
+	fn busy(&self) -> bool {
+		self.processes.iter().chain(&self.retiring).any(Process::busy)
 	}
 
-	fn update(&mut self, options: TaskOptions) {
+	// Scaled down processes are stopping, they are not handed over either
+	fn in_flight(&self) -> Option<String> {
+		self.processes.iter().find_map(Process::in_flight)
+			.or_else(|| self.retiring.iter().find(|process| process.busy()).map(|process| format!("{}[{}] to stop", self.name, process.index)))
+	}
+
+	// With the options, for acting on a single process
+	fn process_mut(&mut self, index: usize) -> Result<(&mut Process, &TaskOptions), String> {
+		match self.processes.get_mut(index) {
+			Some(process) => Ok((process, &self.options)),
+			None => Err(format!("{} has no process {index}", self.name)),
+		}
+	}
+
+	// None goes back to the numprocs of the config
+	fn scale(&mut self, numprocs: Option<u64>) -> String {
+		let configured = self.scaled_from.take().unwrap_or(self.options.numprocs);
+		self.scaled_from = numprocs.map(|_| configured);
+		self.options.numprocs = numprocs.unwrap_or(configured);
+
+		while self.processes.len() > self.options.numprocs as usize {
+			let mut process = self.processes.pop().unwrap();
+			process.graceful_stop(&self.options);
+			self.retiring.push(process);
+		}
+		self.retiring.sort_by_key(|process| process.index);
+		// Added processes start along with the others, a stopped program stays stopped
+		let from = self.processes.len();
+		self.grow();
+		if self.started {
+			for process in &mut self.processes[from..] {
+				process.start(&self.options);
+			}
+		}
+
+		match self.scaled_from {
+			Some(configured) => format!("Scaled {} to {} processes ({configured} in the config)", self.name, self.options.numprocs),
+			None => format!("Scaled {} back to the {} processes of the config", self.name, self.options.numprocs),
+		}
+	}
+
+	fn update(&mut self, mut options: TaskOptions) {
+		// A scale outlives reloads until cleared
+		if self.scaled_from.is_some() {
+			self.scaled_from = Some(options.numprocs);
+			options.numprocs = self.options.numprocs;
+		}
 		if self.options == options {
 			return;
 		}
 
 		self.stop();
+		self.events = match (self.events.take(), options.eventlistener) {
+			(Some(mut pool), true) => {
+				pool.configure(options.events.clone(), options.buffer_size);
+				Some(pool)
+			}
+			(None, true) => Some(EventPool::new(self.name.clone(), options.events.clone(), options.buffer_size)),
+			(_, false) => None,
+		};
 		self.options = options;
 		self.start();
 	}
 
 	fn health_check(&mut self) {
-		for process in &mut self.processes {
+		for process in self.processes.iter_mut().chain(&mut self.retiring) {
 			process.health_check(&self.options);
 		}
+		self.retiring.retain(Process::busy);
+		if let Some(pool) = &mut self.events {
+			pool.poll(self.processes.iter_mut().chain(&mut self.retiring).filter_map(|process| process.listener.as_mut()));
+		}
+	}
+
+	// To one process, or to all of them
+	fn send(&mut self, process: Option<usize>, data: &[u8]) -> Result<(), String> {
+		if self.processes.is_empty() || process.is_some_and(|index| index >= self.processes.len()) {
+			return Err(format!("{}[{}] is not running", self.name, process.unwrap_or(0)));
+		}
+		for index in process.map_or(0..self.processes.len(), |index| index..index + 1) {
+			self.processes[index].write_stdin(data, &self.options)?;
+		}
+		Ok(())
+	}
+
+	// Every process is tried, those that could not be signaled are reported along with the others
+	fn signal(&mut self, process: Option<usize>, signal: libc::c_int) -> Result<String, String> {
+		if let Some(index) = process.filter(|index| *index >= self.processes.len()) {
+			return Err(format!("{} has no process {index}", self.name));
+		}
+
+		let mut signaled = Vec::new();
+		let mut failed = Vec::new();
+		for process in self.processes.iter().filter(|p| process.is_none_or(|index| p.index == index)) {
+			let Some(child) = &process.process else { continue };
+			match child.signal(signal) {
+				Ok(()) => signaled.push(format!("{}[{}] (pid {})", self.name, process.index, child.id())),
+				Err(err) => failed.push(format!("{}[{}]: {err}", self.name, process.index)),
+			}
+		}
+		match (signaled.is_empty(), failed.is_empty()) {
+			(true, true) => Err(format!("{} is not running", self.name)),
+			(false, true) => Ok(format!("Signaled {}", signaled.join(", "))),
+			(true, false) => Err(format!("Could not signal {}", failed.join(", "))),
+			(false, false) => Err(format!("Signaled {}, could not signal {}", signaled.join(", "), failed.join(", "))),
+		}
 	}
 
-	fn status(&self, ident: &str) -> String {
+	// With the children of every process on the system, the descendants of each process are listed
+	fn status(&self, ident: &str, tree: Option<&HashMap<u32, Vec<u32>>>) -> String {
 		let mut status = String::new();
 
-		for i in 0..self.processes.len() {
-			status.push_str(&format!("{ident}[{i}] -> {}\n", self.processes[i].status(&self.options)));
+		let processes = self.processes.iter().map(|process| (process, ""));
+		for (process, retiring) in processes.chain(self.retiring.iter().map(|process| (process, " (scaled down)"))) {
+			status.push_str(&format!("{ident}[{}] -> {}{retiring}\n", process.index, process.status()));
+			for orphan in &process.orphans {
+				status.push_str(&format!("{ident}    orphan {}", orphan_line(*orphan)));
+			}
+			if let (Some(children), Some(child)) = (tree, &process.process) {
+				status.push_str(&stats::tree(child.id(), children, &format!("{ident}    ")));
+			}
 		}
 
 		status
 	}
+
+	// Name and id, and the numprocs set by scale
+	fn label(&self) -> String {
+		let buffered = self.events.as_ref().map(|pool| format!(", {} events buffered", pool.buffered())).unwrap_or_default();
+		match self.scaled_from {
+			Some(configured) => format!("{} (id {}, scaled to {} from {configured}{})", self.name, self.id, self.options.numprocs, buffered),
+			None => format!("{} (id {}{})", self.name, self.id, buffered),
+		}
+	}
 }
 
 struct TaskFile {
@@ -259,46 +1053,33 @@ struct TaskFile {
 	tasks: HashMap<String, Task>,
 }
 
-fn parse_signal(sig: &str) -> Option<libc::c_int>{
-	match sig {
-		"HUP" => Some(1),
-		"INT" => Some(2),
-		"QUIT" => Some(3),
-		"ILL" => Some(4),
-		"TRAP" => Some(5),
-		"ABRT" => Some(6),
-		"EMT" => Some(7),
-		"FPE" => Some(8),
-		"KILL" => Some(9),
-		"BUS" => Some(10),
-		"SEGV" => Some(11),
-		"SYS" => Some(12),
-		"PIPE" => Some(13),
-		"ALRM" => Some(14),
-		"TERM" => Some(15),
-		"URG" => Some(16),
-		"STOP" => Some(17),
-		"TSTP" => Some(18),
-		"CONT" => Some(19),
-		"CHLD" => Some(20),
-		"TTIN" => Some(21),
-		"TTOU" => Some(22),
-		"IO" => Some(23),
-		"XCPU" => Some(24),
-		"XFSZ" => Some(25),
-		"VTALRM" => Some(26),
-		"PROF" => Some(27),
-		"WINCH" => Some(28),
-		"INFO" => Some(29),
-		"USR1" => Some(30),
-		"USR2" => Some(31),
-		_ => None,
+// A number of bytes, or a number followed by K, M or G
+fn parse_size(value: &yaml_rust::Yaml) -> Result<Option<u64>, &'static str> {
+	if let Some(n) = value.as_i64() {
+		return u64::try_from(n).map(Some).map_err(|_| "A size cannot be negative");
 	}
+	let Some(size) = value.as_str() else {
+		return if value.is_badvalue() { Ok(None) } else { Err("A size need to be a number of bytes") };
+	};
+
+	let size = size.trim();
+	let (digits, unit) = size.split_at(size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len()));
+	let shift = match unit.trim() {
+		"" | "B" => 0,
+		"K" | "KB" => 10,
+		"M" | "MB" => 20,
+		"G" | "GB" => 30,
+		_ => return Err("Invalid size unit, expected K, M or G"),
+	};
+	digits.parse::<u64>().ok()
+		.and_then(|n| n.checked_mul(1 << shift))
+		.map(Some)
+		.ok_or("Invalid size")
 }
 
 impl TaskFile {
 	// TODO remove unwrap and expect
-	fn from_yaml(path: &str) -> Result<TaskFile, &str> {
+	fn from_yaml(path: &str) -> Result<TaskFile, String> {
 		let mut task_file = TaskFile {
 			path: path.to_owned(),
 			tasks: HashMap::new(),
@@ -306,6 +1087,10 @@ impl TaskFile {
 
 		let config_file = std::fs::read_to_string(path)
 			.map_err(|_| "Could not open file")?;
+		// %(here)s in the templates
+		let here = std::fs::canonicalize(path).ok()
+			.and_then(|path| path.parent().map(|dir| dir.to_string_lossy().into_owned()))
+			.unwrap_or_else(|| ".".to_owned());
 
 		let config = yaml_rust::YamlLoader::load_from_str(config_file.as_str())
 			.map_err(|_| "Could not parse config file")?;
@@ -331,9 +1116,43 @@ impl TaskFile {
 						"always" => TaskOptionAutoRestart::Always,
 						"unexpected" => TaskOptionAutoRestart::Unexpected(exitcodes),
 						"never" => TaskOptionAutoRestart::Never,
-						_ => return Err("Invalid autorestart value")
+						_ => return Err("Invalid autorestart value".to_owned())
+					};
+
+					let eventlistener = get_optional!(value, "eventlistener", as_bool, false);
+					if eventlistener && !value["stdout"].is_badvalue() {
+						return Err("An eventlistener cannot redirect its stdout".to_owned());
+					}
+					let events = match value["events"].as_vec() {
+						Some(events) => events.iter()
+							.map(|e| e.as_str().map(|s| s.to_owned()))
+							.collect::<Option<Vec<String>>>()
+							.ok_or("events need to be a list of event names")?,
+						None => vec!["PROCESS_STATE".to_owned()],
 					};
 
+					let stdin = match value["stdin"].as_str() {
+						None | Some("null") => TaskOptionStdin::Null,
+						Some("pipe") => TaskOptionStdin::Pipe,
+						Some(path) => TaskOptionStdin::File(path.to_owned()),
+					};
+					if eventlistener && stdin != TaskOptionStdin::Null {
+						return Err("An eventlistener cannot redirect its stdin".to_owned());
+					}
+
+					let stopsignal = match &value["stopsignal"] {
+						yaml_rust::Yaml::Integer(signal) => parse_signal(&signal.to_string()),
+						signal => parse_signal(signal.as_str().unwrap_or("TERM")),
+					}.ok_or("Invalid stopsignal")?;
+
+					let limits = limits::from_yaml(&value["limits"])?;
+
+					let sockets = SocketOptions::from_yaml(&value["sockets"])?;
+					// The shim handing the sockets over is the daemon binary, out of reach in a chroot
+					if !sockets.is_empty() && !value["sandbox"]["chroot"].is_badvalue() {
+						return Err("sockets cannot be used with sandbox.chroot".to_owned());
+					}
+
 					let env: HashMap<String, String> = value["env"].as_hash()
 						.map(|h| h.iter().filter_map(|(k, v)| {
 							if let (Some(a), Some(b)) = (k.as_str(), v.as_str()) {
@@ -342,22 +1161,50 @@ impl TaskFile {
 							None
 						}).collect()).unwrap_or(HashMap::new());
 
-					task_file.tasks.insert(name.to_owned(),
-					Task::new(TaskOptions {
+					let numprocs_start = u64::try_from(get_optional!(value, "numprocs_start", as_i64, 0))
+						.map_err(|_| "numprocs_start need to be a positive number")?;
+
+					let options = TaskOptions {
 						argv,
 						numprocs: get_optional!(value, "numprocs", as_i64, 1) as u64,
+						numprocs_start,
+						here: here.clone(),
 						autostart: get_optional!(value, "autostart", as_bool, true),
 						autorestart,
 						starttime_sec: get_optional!(value, "starttime", as_i64, 0) as u64,
 						retries: get_optional!(value, "retries", as_i64, 8) as u64,
-						stopsignal: parse_signal(get_optional!(value, "stopsignal", as_str, "TERM")).ok_or("Invalid stopsignal")?,
+						stopsignal,
 						stoptime_sec: get_optional!(value, "stoptime", as_i64, 0) as u64,
 						stdout: value["stdout"].as_str().map(|s| s.to_owned()),
 						stderr: value["stderr"].as_str().map(|s| s.to_owned()),
+						stdin,
 						env,
 						workingdir: value["workingdir"].as_str().map(|s| s.to_owned()),
 						umask: u16::from_str_radix(get_optional!(value, "umask", as_i64, 777).to_string().as_str(), 8).unwrap_or(0o777),
-					}));
+						eventlistener,
+						events,
+						buffer_size: get_optional!(value, "buffer_size", as_i64, 10).max(1) as usize,
+						pre_start: value["pre_start"].as_str().map(|s| s.to_owned()),
+						post_start: value["post_start"].as_str().map(|s| s.to_owned()),
+						pre_stop: value["pre_stop"].as_str().map(|s| s.to_owned()),
+						post_stop: value["post_stop"].as_str().map(|s| s.to_owned()),
+						hook_timeout_sec: get_optional!(value, "hook_timeout", as_i64, 30) as u64,
+						readiness: ProbeOptions::from_yaml(&value["readiness"])?,
+						liveness: ProbeOptions::from_yaml(&value["liveness"])?,
+						max_rss: parse_size(&value["max_rss"])?,
+						max_cpu_percent: value["max_cpu_percent"].as_f64().or(value["max_cpu_percent"].as_i64().map(|n| n as f64)),
+						cpu_window_sec: get_optional!(value, "cpu_window", as_i64, 60).max(1) as u64,
+						max_open_fds: value["max_open_fds"].as_i64().map(|n| n as u64),
+						limits,
+						cgroup: CgroupOptions::from_yaml(value)?,
+						sched: SchedOptions::from_yaml(value)?,
+						sandbox: SandboxOptions::from_yaml(&value["sandbox"])?,
+						sockets,
+					};
+					// Unknown variables are reported now instead of at every spawn
+					options.instance(name, 0).map_err(|err| format!("{name}: {err}"))?;
+
+					task_file.tasks.insert(name.to_owned(), Task::new(name.to_owned(), options));
 				}
 			}
 		}
@@ -383,7 +1230,8 @@ impl TaskFile {
 		}
 	}
 
-	fn update(&mut self, updated_task_file: TaskFile) {
+	// The tasks gone from the file, stopping
+	fn update(&mut self, updated_task_file: TaskFile) -> Vec<Task> {
 		let mut new_tasks = HashMap::new();
 
 		for (name, task) in updated_task_file.tasks.iter() {
@@ -391,7 +1239,7 @@ impl TaskFile {
 				old_task.update(task.options.clone());
 				new_tasks.insert(name.to_owned(), old_task);
 			} else {
-				let mut new_task = Task::new(task.options.clone());
+				let mut new_task = Task::new(name.to_owned(), task.options.clone());
 				new_task.init();
 				new_tasks.insert(name.to_owned(), new_task);
 			}
@@ -401,13 +1249,12 @@ impl TaskFile {
 			task.stop();
 		}
 
-		self.tasks = new_tasks;
+		std::mem::replace(&mut self.tasks, new_tasks).into_values().collect()
 	}
 
-	fn reload(&mut self) -> Result<(), String> {
+	fn reload(&mut self) -> Result<Vec<Task>, String> {
 		let task_file = TaskFile::from_yaml(&self.path)?;
-		self.update(task_file);
-		Ok(())
+		Ok(self.update(task_file))
 	}
 
 	fn health_check(&mut self) {
@@ -417,14 +1264,27 @@ impl TaskFile {
 	}
 }
 
+// pid, state and command line of an orphan
+fn orphan_line(pid: u32) -> String {
+	let Ok(stat) = procfs::stat(pid) else { return format!("{pid} (gone)\n") };
+	let cmdline = procfs::cmdline(pid).ok().filter(|cmd| !cmd.is_empty()).unwrap_or(format!("[{}]", stat.comm));
+	format!("{pid} ({}) {cmdline}\n", stat.state)
+}
+
 struct TaskFiles {
-	tasks_files: HashMap<String, TaskFile>
+	tasks_files: HashMap<String, TaskFile>,
+	reaper: Reaper,
+	orphans: Vec<u32>, // running orphans that could not be traced back to a program
+	removed: Vec<Task>, // unloaded or gone from their file, kept until their stop and hooks are done
 }
 
 impl TaskFiles {
 	fn new() -> TaskFiles {
 		TaskFiles {
 			tasks_files: HashMap::new(),
+			reaper: Reaper::new(),
+			orphans: Vec::new(),
+			removed: Vec::new(),
 		}
 	}
 
@@ -432,11 +1292,14 @@ impl TaskFiles {
 		match TaskFile::from_yaml(path) {
 			Ok(mut new_task_file) => {
 				if let Some(task_file) = self.tasks_files.get_mut(path) {
-					task_file.update(new_task_file);
+					let removed = task_file.update(new_task_file);
+					self.removed.extend(removed);
 				} else {
 					new_task_file.init();
 					self.tasks_files.insert(new_task_file.path.clone(), new_task_file);
 				}
+				events::emit(TaskmasterEvent::FileLoaded(path.to_owned()));
+				self.release_sockets();
 				Ok(())
 			}
 			Err(err) => {
@@ -445,56 +1308,192 @@ impl TaskFiles {
 		}
 	}
 
+	fn restore(&mut self, state: SavedState) {
+		for file in state.files {
+			match TaskFile::from_yaml(&file.path) {
+				Ok(mut task_file) => {
+					for task in task_file.tasks.values_mut() {
+						if let Some((_, numprocs)) = file.scaled.iter().find(|(name, _)| *name == task.name) {
+							task.scaled_from = Some(task.options.numprocs);
+							task.options.numprocs = *numprocs;
+						}
+						task.restore(&file.processes);
+					}
+					self.tasks_files.insert(file.path.clone(), task_file);
+					events::emit(TaskmasterEvent::FileLoaded(file.path));
+				}
+				Err(err) => eprintln!("Could not restore {}: {err}", file.path),
+			}
+		}
+	}
+
+	fn collect_state(&self, save: impl Fn(&Process) -> Result<SavedProcess, String>) -> Result<SavedState, String> {
+		let mut files: Vec<SavedFile> = self.tasks_files.values().map(|task_file| {
+			let mut processes: Vec<SavedProcess> = task_file.tasks.values()
+				.flat_map(|task| task.processes.iter().map(&save))
+				.collect::<Result<_, _>>()?;
+			processes.sort_by(|a, b| (&a.program, a.index).cmp(&(&b.program, b.index)));
+			let mut scaled: Vec<(String, u64)> = task_file.tasks.values()
+				.filter(|task| task.scaled_from.is_some())
+				.map(|task| (task.name.clone(), task.options.numprocs))
+				.collect();
+			scaled.sort();
+			Ok(SavedFile { path: task_file.path.clone(), processes, scaled })
+		}).collect::<Result<_, String>>()?;
+
+		files.sort_by(|a, b| a.path.cmp(&b.path));
+		Ok(SavedState { files })
+	}
+
+	fn saved_state(&self) -> SavedState {
+		self.collect_state(|process| Ok(process.saved())).unwrap_or_default()
+	}
+
 	fn unload(&mut self, path: &str) {
 		if let Some(mut deleted) = self.tasks_files.remove(path) {
 			deleted.stop();
+			self.removed.extend(deleted.tasks.into_values());
+			events::emit(TaskmasterEvent::FileUnloaded(path.to_owned()));
 		}
+		self.release_sockets();
+	}
+
+	fn release_sockets(&self) {
+		sockets::release_unused(self.tasks_files.values()
+			.flat_map(|task_file| task_file.tasks.values())
+			.flat_map(|task| task.options.sockets.addresses()));
 	}
 
 	fn health_check(&mut self) {
 		for task_file in self.tasks_files.values_mut() {
 			task_file.health_check();
 		}
+		for task in &mut self.removed {
+			task.health_check();
+		}
+		self.removed.retain(Task::busy);
+		self.reap_orphans();
 	}
 
-	fn status(&self) -> String {
+	// Zombies are reaped, the others are shown with the process they came from
+	fn reap_orphans(&mut self) {
+		let spawned: HashSet<u32> = self.tasks_files.values()
+			.flat_map(|task_file| task_file.tasks.values())
+			.chain(&self.removed)
+			.flat_map(|task| task.processes.iter().chain(&task.retiring))
+			.flat_map(Process::pids)
+			.collect();
+		let Some(orphans) = self.reaper.scan(&spawned) else { return };
+
+		let mut processes: Vec<&mut Process> = self.tasks_files.values_mut()
+			.flat_map(|task_file| task_file.tasks.values_mut())
+			.chain(&mut self.removed)
+			.flat_map(|task| task.processes.iter_mut().chain(&mut task.retiring))
+			.collect();
+		for process in &mut processes {
+			process.orphans.clear();
+		}
+		self.orphans.clear();
+
+		for orphan in orphans {
+			let owner = processes.iter_mut().find(|process| process.owns(&orphan));
+			if orphan.state == 'Z' {
+				let status = orphan.reap();
+				match owner {
+					Some(process) => println!("{}[{}]: reaped orphan {} with {status}", process.program, process.index, orphan.pid),
+					None => println!("Reaped orphan {} with {status}", orphan.pid),
+				}
+				continue;
+			}
+			match owner {
+				Some(process) => process.orphans.push(orphan.pid),
+				None => self.orphans.push(orphan.pid),
+			}
+		}
+	}
+
+	fn status(&self, tree: bool) -> String {
 		let mut status = String::new();
+		let children = tree.then(procfs::children);
 
 		for task_file in self.tasks_files.values() {
 			if !status.is_empty() {
-				status.push_str("\n");
+				status.push('\n');
 			}
 			status.push_str(&format!("{}:\n", task_file.path));
-			for (name, task) in task_file.tasks.iter() {
+			for task in task_file.tasks.values() {
 				status.push_str(&format!(
-					"\n  {name} (id {}):\n{}",
-					task.id,
-					task.status("    ")
+					"\n  {}:\n{}",
+					task.label(),
+					task.status("    ", children.as_ref())
 				));
 			}
 		}
+		if !self.orphans.is_empty() {
+			status.push_str("\norphans:\n");
+			for orphan in &self.orphans {
+				status.push_str(&format!("  {}", orphan_line(*orphan)));
+			}
+		}
 
 		status
 	}
 
 	fn reload(&mut self) -> TaskmasterDaemonResult {
 		let mut errors = String::new();
+		let mut reloaded = 0;
 
 		for task_file in self.tasks_files.values_mut() {
-			if let Err(err) = task_file.reload() {
-				errors.push_str(format!("\n  - Failed to reload {}: {}", task_file.path, err).as_str());
+			match task_file.reload() {
+				Ok(removed) => {
+					self.removed.extend(removed);
+					reloaded += 1;
+				}
+				Err(err) => errors.push_str(format!("\n  - Failed to reload {}: {}", task_file.path, err).as_str()),
 			}
 		}
-		if errors.len() > 0 {
+		// A file that failed to reload keeps running its old config
+		if reloaded > 0 {
+			events::emit(TaskmasterEvent::ConfigReloaded);
+		}
+		self.release_sockets();
+		if !errors.is_empty() {
 			TaskmasterDaemonResult::Err(errors)
 		} else {
 			TaskmasterDaemonResult::Success
 		}
 	}
 
+	fn snapshot(&self) -> Vec<ProcessSnapshot> {
+		let mut processes: Vec<ProcessSnapshot> = self.tasks_files.values()
+			.flat_map(|task_file| task_file.tasks.values())
+			.flat_map(|task| task.processes.iter().map(|process| process.snapshot(task.id)))
+			.collect();
+
+		processes.sort_by_key(|process| (process.task_id, process.process));
+		processes
+	}
+
+	fn in_flight(&self) -> Option<String> {
+		self.tasks_files.values().flat_map(|task_file| task_file.tasks.values()).find_map(Task::in_flight)
+			.or_else(|| self.removed.first().map(|task| format!("the removed {} to stop", task.name)))
+	}
+
+	// Kill everything and wait for the pre_stop and post_stop hooks, nothing is served anymore
+	fn shutdown(&mut self) {
+		for task_file in self.tasks_files.values_mut() {
+			task_file.stop();
+		}
+		self.removed.extend(self.tasks_files.drain().flat_map(|(_, task_file)| task_file.tasks.into_values()));
+		while !self.removed.is_empty() {
+			self.health_check();
+			handle::wait_for_exit(HEALTH_INTERVAL);
+		}
+	}
+
 	fn find_by_id(&mut self, id: usize) -> Option<&mut Task> {
-		for (_, task_file) in &mut self.tasks_files {
-			for (_, task) in &mut task_file.tasks {
+		for task_file in self.tasks_files.values_mut() {
+			for task in task_file.tasks.values_mut() {
 				if task.id == id {
 					return Some(task);
 				}
@@ -514,18 +1513,61 @@ fn bind(path: &str) -> std::io::Result<UnixListener> {
 	UnixListener::bind(path)
 }
 
+// Read the last lines of a file without loading all of it
+fn tail(path: &str, lines: usize) -> std::io::Result<String> {
+	const CHUNK: u64 = 8192;
+
+	let mut file = File::open(path)?;
+	let mut pos = file.seek(SeekFrom::End(0))?;
+	let mut buf: Vec<u8> = Vec::new();
+
+	// One more newline than asked for, the file usually ends with one
+	while pos > 0 && buf.iter().filter(|c| **c == b'\n').count() <= lines {
+		let len = CHUNK.min(pos);
+		pos -= len;
+		file.seek(SeekFrom::Start(pos))?;
+
+		let mut chunk = vec![0u8; len as usize];
+		file.read_exact(&mut chunk)?;
+		chunk.append(&mut buf);
+		buf = chunk;
+	}
+
+	let text = String::from_utf8_lossy(&buf);
+	let start = text.lines().count().saturating_sub(lines);
+	Ok(text.lines().skip(start).fold(String::new(), |acc, line| acc + line + "\n"))
+}
+
+const HEALTH_INTERVAL: Duration = Duration::from_millis(50);
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(60);
+const ROLLING_MARGIN_SEC: u64 = 10;
+const PID_FILE: &str = "/tmp/taskmasterd.pid";
+
+// Handed over to the new binary on upgrade
+static LISTENER: AtomicI32 = AtomicI32::new(-1);
+
 lazy_static! {
 	static ref TASKS: Arc<Mutex<TaskFiles>> = Arc::new(Mutex::new(TaskFiles::new()));
 }
 
+fn hello(version: u32) -> TaskmasterDaemonResult {
+	match negotiate_version(version) {
+		Some(version) => TaskmasterDaemonResult::Hello(version),
+		None => TaskmasterDaemonResult::Err(format!(
+			"Incompatible protocol version {version} (taskmasterd speaks {PROTOCOL_VERSION})"
+		))
+	}
+}
+
 fn handle_client_request(tasks: &mut MutexGuard<TaskFiles>, req: TaskmasterDaemonRequest) -> TaskmasterDaemonResult {
 	match req {
-		TaskmasterDaemonRequest::Status => {
+		TaskmasterDaemonRequest::Hello(version) => hello(version),
+		TaskmasterDaemonRequest::Status | TaskmasterDaemonRequest::StatusTree => {
 			if tasks.tasks_files.is_empty() {
 				return TaskmasterDaemonResult::Ok("No tasks loaded yet".to_owned());
 			}
 
-			TaskmasterDaemonResult::Raw(tasks.status())
+			TaskmasterDaemonResult::Raw(tasks.status(matches!(req, TaskmasterDaemonRequest::StatusTree)))
 		},
 		TaskmasterDaemonRequest::Reload => {
 			tasks.reload()
@@ -538,9 +1580,8 @@ fn handle_client_request(tasks: &mut MutexGuard<TaskFiles>, req: TaskmasterDaemo
 			TaskmasterDaemonResult::Success
 		},
 		TaskmasterDaemonRequest::Stop => {
-			for task_file in tasks.tasks_files.values_mut() {
-				task_file.stop();
-			}
+			tasks.shutdown();
+			state::clear();
 			std::process::exit(0);
 		},
 		TaskmasterDaemonRequest::StartTask(id) => {
@@ -565,13 +1606,36 @@ fn handle_client_request(tasks: &mut MutexGuard<TaskFiles>, req: TaskmasterDaemo
 			}
 			TaskmasterDaemonResult::Err("Task not found".to_owned())
 		}
-		TaskmasterDaemonRequest::InfoTask(id) => {
+		TaskmasterDaemonRequest::StartProcess{id, process} | TaskmasterDaemonRequest::StopProcess{id, process} | TaskmasterDaemonRequest::RestartProcess{id, process} => {
+			let Some(task) = tasks.find_by_id(id) else {
+				return TaskmasterDaemonResult::Err("Task not found".to_owned());
+			};
+			// Processes added by scale follow the ones started by hand
+			if !matches!(req, TaskmasterDaemonRequest::StopProcess{..}) && process < task.processes.len() {
+				task.started = true;
+			}
+			let (process, options) = match task.process_mut(process) {
+				Ok(found) => found,
+				Err(err) => return TaskmasterDaemonResult::Err(err),
+			};
+			match req {
+				TaskmasterDaemonRequest::StartProcess{..} => process.start(options),
+				TaskmasterDaemonRequest::StopProcess{..} => process.graceful_stop(options),
+				_ => {
+					process.stop(options);
+					process.start(options);
+				}
+			}
+			TaskmasterDaemonResult::Success
+		}
+		TaskmasterDaemonRequest::InfoTask(id) | TaskmasterDaemonRequest::InfoTaskTree(id) => {
 			if let Some(task) = tasks.find_by_id(id) {
+				let children = matches!(req, TaskmasterDaemonRequest::InfoTaskTree(_)).then(procfs::children);
 				return TaskmasterDaemonResult::Raw(
 					format!(
 						"{:?}\n{}",
 						task.options,
-						task.status("  ")
+						task.status("  ", children.as_ref())
 					)
 				)
 			}
@@ -580,14 +1644,371 @@ fn handle_client_request(tasks: &mut MutexGuard<TaskFiles>, req: TaskmasterDaemo
 		TaskmasterDaemonRequest::LoadFile(path) => {
 			match tasks.load(&path) {
 				Ok(_) => TaskmasterDaemonResult::Success,
-				Err(err) => return TaskmasterDaemonResult::Err(err)
+				Err(err) => TaskmasterDaemonResult::Err(err)
 			}
 		},
 		TaskmasterDaemonRequest::UnloadFile(path) => {
 			tasks.unload(&path);
 			TaskmasterDaemonResult::Success
 		},
-		_ => TaskmasterDaemonResult::Err("Not implemented".to_owned())
+		TaskmasterDaemonRequest::LogsTask{id, stream, lines} | TaskmasterDaemonRequest::LogsProcess{id, stream, lines, ..} => {
+			if let Some(task) = tasks.find_by_id(id) {
+				// The log of the first process when the path depends on %(process_num)d and none is given
+				let process = match req {
+					TaskmasterDaemonRequest::LogsProcess{process, ..} => process,
+					_ => 0,
+				};
+				if process >= task.options.numprocs as usize {
+					return TaskmasterDaemonResult::Err(format!("{} has no process {process}", task.name));
+				}
+				let options = match task.options.instance(&task.name, process) {
+					Ok(options) => options,
+					Err(err) => return TaskmasterDaemonResult::Err(err),
+				};
+				let (name, path) = match stream {
+					LogStream::Stdout => ("stdout", &options.stdout),
+					LogStream::Stderr => ("stderr", &options.stderr),
+				};
+				let Some(path) = path else {
+					return TaskmasterDaemonResult::Err(format!("No {name} file configured"));
+				};
+				return match tail(path, lines) {
+					Ok(text) => TaskmasterDaemonResult::Raw(text),
+					Err(err) => TaskmasterDaemonResult::Err(format!("Could not read {path}: {err}")),
+				}
+			}
+			TaskmasterDaemonResult::Err("Task not found".to_owned())
+		}
+		TaskmasterDaemonRequest::Snapshot => TaskmasterDaemonResult::Snapshot(tasks.snapshot()),
+		TaskmasterDaemonRequest::ScaleTask{program, numprocs} => {
+			let scaled: Vec<String> = tasks.tasks_files.values_mut()
+				.filter_map(|task_file| task_file.tasks.get_mut(&program))
+				.map(|task| task.scale(numprocs.map(|numprocs| numprocs as u64)))
+				.collect();
+			if scaled.is_empty() {
+				return TaskmasterDaemonResult::Err(format!("No program named {program}"));
+			}
+			TaskmasterDaemonResult::Ok(scaled.join("\n"))
+		}
+		TaskmasterDaemonRequest::SendTask{id, process, data} => {
+			if let Some(task) = tasks.find_by_id(id) {
+				return match task.send(process, &data) {
+					Ok(()) => TaskmasterDaemonResult::Success,
+					Err(err) => TaskmasterDaemonResult::Err(err),
+				}
+			}
+			TaskmasterDaemonResult::Err("Task not found".to_owned())
+		}
+		TaskmasterDaemonRequest::SignalTask{id, process, signal} => {
+			let Some(signal) = parse_signal(&signal) else {
+				return TaskmasterDaemonResult::Err(format!("Unknown signal {signal}"));
+			};
+			if let Some(task) = tasks.find_by_id(id) {
+				return match task.signal(process, signal) {
+					Ok(signaled) => TaskmasterDaemonResult::Ok(signaled),
+					Err(err) => TaskmasterDaemonResult::Err(err),
+				}
+			}
+			TaskmasterDaemonResult::Err("Task not found".to_owned())
+		}
+		// Subscriptions and attachments take over the connection, see handle_client
+		TaskmasterDaemonRequest::Subscribe(_) => TaskmasterDaemonResult::Err("Cannot subscribe here".to_owned()),
+		TaskmasterDaemonRequest::Attach{..} => TaskmasterDaemonResult::Err("Cannot attach here".to_owned()),
+		TaskmasterDaemonRequest::Upgrade => TaskmasterDaemonResult::Err("Cannot upgrade here".to_owned()),
+		// Answered once every batch is up, see rolling_restart
+		TaskmasterDaemonRequest::RestartTaskRolling{..} => TaskmasterDaemonResult::Err("Cannot restart here".to_owned()),
+		TaskmasterDaemonRequest::Input(_) => TaskmasterDaemonResult::Err("Not attached to any process".to_owned()),
+	}
+}
+
+// Push events until the client goes away
+fn stream_events(stream: &mut UnixStream, program: Option<String>) {
+	let events = events::subscribe(program);
+
+	if write_frame(stream, &TaskmasterDaemonResult::Success).is_err() {
+		return;
+	}
+	loop {
+		match events.recv_timeout(Duration::from_secs(1)) {
+			Ok(event) => {
+				if write_frame(stream, &TaskmasterDaemonResult::Event(event)).is_err() {
+					break;
+				}
+			}
+			// Without events a closed connection is only seen by looking at it
+			Err(mpsc::RecvTimeoutError::Timeout) if !peer_closed(stream) => {}
+			Err(_) => break,
+		}
+	}
+}
+
+// Nothing is ever sent on a subscription, so anything readable is the end of it
+fn peer_closed(stream: &UnixStream) -> bool {
+	let mut poll = libc::pollfd { fd: stream.as_raw_fd(), events: libc::POLLIN | libc::POLLRDHUP, revents: 0 };
+	unsafe { libc::poll(&mut poll, 1, 0) != 0 }
+}
+
+fn attach_output(tasks: &Arc<Mutex<TaskFiles>>, id: usize, process: usize) -> Result<(String, mpsc::Receiver<output::Chunk>), String> {
+	let mut tasks = tasks.lock().unwrap();
+	let task = tasks.find_by_id(id).ok_or("Task not found")?;
+	if task.options.stdin != TaskOptionStdin::Pipe {
+		return Err(format!("The stdin of {} is not a pipe", task.name));
+	}
+
+	let message = format!("Attached to {}[{process}]", task.name);
+	let (process, _) = task.process_mut(process)?;
+	Ok((message, process.output.attach()))
+}
+
+// Forward input frames to the stdin of a process and stream its output back, until the client stops sending
+fn attach(stream: &mut UnixStream, tasks: &Arc<Mutex<TaskFiles>>, id: usize, process: usize) {
+	let (message, output) = match attach_output(tasks, id, process) {
+		Ok(attached) => attached,
+		Err(err) => {
+			let _ = write_frame(stream, &TaskmasterDaemonResult::Err(err));
+			return;
+		}
+	};
+	let Ok(mut input) = stream.try_clone() else { return };
+	if write_frame(stream, &TaskmasterDaemonResult::Ok(message)).is_err() {
+		return;
+	}
+
+	// Only this thread writes to the client, errors of the input side go through the channel
+	let (errors, received) = mpsc::channel();
+	let tasks = tasks.clone();
+	thread::spawn(move || loop {
+		let err = match read_frame::<_, TaskmasterDaemonRequest>(&mut input) {
+			Ok(TaskmasterDaemonRequest::Input(data)) => {
+				let mut tasks = tasks.lock().unwrap();
+				match tasks.find_by_id(id) {
+					Some(task) => task.send(Some(process), &data).err(),
+					None => Some("Task not found".to_owned()),
+				}
+			}
+			Ok(_) => Some("Only input can be sent while attached".to_owned()),
+			Err(FrameError::Decode(err)) => Some(format!("Unknown request: {err}")),
+			Err(_) => break,
+		};
+		if let Some(err) = err {
+			if errors.send(err).is_err() {
+				break;
+			}
+		}
+	});
+
+	let mut attached = true;
+	loop {
+		while attached {
+			match received.try_recv() {
+				Ok(err) => if write_frame(stream, &TaskmasterDaemonResult::Err(err)).is_err() {
+					return;
+				},
+				Err(mpsc::TryRecvError::Empty) => break,
+				Err(mpsc::TryRecvError::Disconnected) => attached = false,
+			}
+		}
+
+		// Output of the last input is still sent after a detach, until the process goes quiet
+		let (stream_name, data) = match output.recv_timeout(Duration::from_millis(100)) {
+			Ok(output) => output,
+			Err(mpsc::RecvTimeoutError::Timeout) if attached => continue,
+			Err(mpsc::RecvTimeoutError::Timeout) => return,
+			Err(mpsc::RecvTimeoutError::Disconnected) => {
+				if attached {
+					let err = "The process is gone or its output was not read fast enough".to_owned();
+					let _ = write_frame(stream, &TaskmasterDaemonResult::Err(err));
+				}
+				return;
+			}
+		};
+		if write_frame(stream, &TaskmasterDaemonResult::Output{stream: stream_name, data}).is_err() {
+			return;
+		}
+	}
+}
+
+fn handle_client(mut stream: UnixStream, tasks: Arc<Mutex<TaskFiles>>) {
+	// The first frame must be a hello, anything else means the client does not speak our protocol
+	let greeting = match read_frame::<_, TaskmasterDaemonRequest>(&mut stream) {
+		Ok(TaskmasterDaemonRequest::Hello(version)) => hello(version),
+		Ok(_) | Err(FrameError::Decode(_)) => TaskmasterDaemonResult::Err("Expected a hello".to_owned()),
+		Err(_) => return,
+	};
+	let accepted = matches!(greeting, TaskmasterDaemonResult::Hello(_));
+
+	if write_frame(&mut stream, &greeting).is_ok() && accepted {
+		loop {
+			let response = match read_frame::<_, TaskmasterDaemonRequest>(&mut stream) {
+				Ok(TaskmasterDaemonRequest::Subscribe(program)) => {
+					stream_events(&mut stream, program);
+					break;
+				}
+				Ok(TaskmasterDaemonRequest::Attach{id, process}) => {
+					attach(&mut stream, &tasks, id, process);
+					break;
+				}
+				Ok(TaskmasterDaemonRequest::Upgrade) => upgrade(&stream, &tasks),
+				Ok(TaskmasterDaemonRequest::RestartTaskRolling{id, batch, wait_ready}) => rolling_restart(&tasks, id, batch, wait_ready),
+				Ok(request) => {
+					println!("read {:?}", request);
+
+					handle_client_request(
+						&mut tasks.lock().unwrap(),
+						request
+					)
+				}
+				Err(FrameError::Decode(err)) => {
+					TaskmasterDaemonResult::Err(format!("Unknown request: {err}"))
+				}
+				Err(_) => break,
+			};
+
+			if write_frame(&mut stream, &response).is_err() {
+				break;
+			}
+		}
+	}
+
+	if let Err(e) = stream.shutdown(std::net::Shutdown::Both) {
+		eprintln!("Failed to shutdown stream: {}", e);
+	}
+}
+
+// The lock is only taken to restart a batch and to check on it, the health loop keeps running in between
+// Longest a batch can take when nothing goes wrong, each step it goes through is bounded
+fn rolling_timeout(opts: &TaskOptions, wait_ready: bool) -> Duration {
+	let hooks = [&opts.pre_stop, &opts.pre_start].into_iter().flatten().count() as u64 * opts.hook_timeout_sec;
+	let ready = opts.readiness.as_ref().filter(|_| wait_ready).map_or(0, ProbeOptions::failing_sec);
+	Duration::from_secs(hooks + opts.stoptime_sec + opts.starttime_sec + ready + ROLLING_MARGIN_SEC)
+}
+
+fn rolling_restart(tasks: &Mutex<TaskFiles>, id: usize, batch: usize, wait_ready: bool) -> TaskmasterDaemonResult {
+	let (name, count) = match tasks.lock().unwrap().find_by_id(id) {
+		Some(task) => (task.name.clone(), task.processes.len()),
+		None => return TaskmasterDaemonResult::Err("Task not found".to_owned()),
+	};
+	let batch = batch.max(1);
+	let batches = count.div_ceil(batch);
+
+	for (n, first) in (0..count).step_by(batch).enumerate() {
+		let indexes = first..(first + batch).min(count);
+		let aborted = |err: String| TaskmasterDaemonResult::Err(format!(
+			"Rolling restart of {name} aborted in batch {} of {batches} (processes {} to {}), {err}", n + 1, indexes.start, indexes.end - 1
+		));
+		let mut previous = Vec::new();
+		let timeout;
+		{
+			let mut tasks = tasks.lock().unwrap();
+			let Some(task) = tasks.find_by_id(id) else {
+				return TaskmasterDaemonResult::Err(format!("{name} was unloaded during the rolling restart"));
+			};
+			timeout = rolling_timeout(&task.options, wait_ready);
+			for process in &mut task.processes[indexes.clone()] {
+				previous.push(process.process.as_ref().map(ProcessHandle::id));
+				match process.process {
+					Some(_) => process.restart(&task.options, "rolling restart".to_owned()),
+					None => process.start(&task.options),
+				}
+			}
+		}
+
+		let deadline = Instant::now() + timeout;
+		loop {
+			thread::sleep(Duration::from_millis(100));
+
+			let mut tasks = tasks.lock().unwrap();
+			let Some(task) = tasks.find_by_id(id) else {
+				return TaskmasterDaemonResult::Err(format!("{name} was unloaded during the rolling restart"));
+			};
+			// Processes removed by a reload in the meantime are done
+			let rolled: Vec<_> = indexes.clone().zip(&previous)
+				.filter_map(|(index, previous)| task.processes.get(index).map(|process| process.rolled(*previous, &task.options, wait_ready)))
+				.collect();
+
+			if let Some(Err(err)) = rolled.iter().flatten().find(|rolled| rolled.is_err()) {
+				return aborted(err.clone());
+			}
+			if rolled.iter().all(Option::is_some) {
+				break;
+			}
+			if Instant::now() >= deadline {
+				return aborted(format!("not back{} after {}s", if wait_ready { " and ready" } else { "" }, timeout.as_secs()));
+			}
+		}
+	}
+	TaskmasterDaemonResult::Ok(format!("Restarted the {count} processes of {name}, {batch} at a time"))
+}
+
+// Replace the daemon by the binary now on disk, only returns when that failed
+// Once nothing is in flight everything stays locked until the exec, the new daemon starts from the exact same state
+fn upgrade(stream: &UnixStream, tasks: &Mutex<TaskFiles>) -> TaskmasterDaemonResult {
+	// Only what is settled is handed over, the health loop keeps going while we wait
+	let deadline = Instant::now() + UPGRADE_TIMEOUT;
+	let tasks = loop {
+		let tasks = tasks.lock().unwrap();
+		match tasks.in_flight() {
+			None => break tasks,
+			Some(_) if Instant::now() < deadline => {
+				drop(tasks);
+				thread::sleep(HEALTH_INTERVAL);
+			}
+			Some(waiting) => return TaskmasterDaemonResult::Err(format!("Could not upgrade, still waiting for {waiting}")),
+		}
+	};
+	let state = match tasks.collect_state(Process::handed_over) {
+		Ok(state) => state,
+		Err(err) => return TaskmasterDaemonResult::Err(err),
+	};
+	let upgrade = state::Upgrade {
+		listener: LISTENER.load(Ordering::Relaxed),
+		client: stream.as_raw_fd(),
+		sockets: sockets::handed_over(),
+		state,
+	};
+
+	let inherited: Vec<RawFd> = upgrade.fds().into_iter().chain(pid_file_fds(PID_FILE)).collect();
+	for fd in &inherited {
+		state::set_cloexec(*fd, false);
+	}
+
+	let err = match (state::hand_over(&upgrade), daemon_exe()) {
+		(Ok((key, value)), Ok(exe)) => {
+			println!("Upgrading to {exe}...");
+			let err = std::process::Command::new(exe).args(std::env::args_os().skip(1)).env(key, &value).exec();
+			if let Ok(fd) = value.parse::<RawFd>() {
+				unsafe { libc::close(fd) };
+			}
+			err.to_string()
+		}
+		(Err(err), _) => err,
+		(_, Err(err)) => err.to_string(),
+	};
+	for fd in &inherited {
+		state::set_cloexec(*fd, true);
+	}
+	TaskmasterDaemonResult::Err(format!("Could not upgrade: {err}"))
+}
+
+// Replaced binaries show up as deleted, the new one is at the same path
+fn daemon_exe() -> std::io::Result<String> {
+	Ok(std::env::current_exe()?.to_string_lossy().trim_end_matches(" (deleted)").to_owned())
+}
+
+// daemonize keeps the pid file locked through its fd
+fn pid_file_fds(path: &str) -> Vec<RawFd> {
+	let Ok(fds) = std::fs::read_dir("/proc/self/fd") else { return Vec::new() };
+
+	fds.flatten()
+		.filter(|fd| std::fs::read_link(fd.path()).is_ok_and(|target| target == std::path::Path::new(path)))
+		.filter_map(|fd| fd.file_name().to_string_lossy().parse::<RawFd>().ok())
+		.collect()
+}
+
+// Processes we spawn must not inherit the lock
+fn cloexec_pid_file(path: &str) {
+	for fd in pid_file_fds(path) {
+		state::set_cloexec(fd, true);
 	}
 }
 
@@ -598,72 +2019,95 @@ fn handler() {
 }
 
 fn main() {
-	if unsafe { libc::getuid() } != 0 {
-		println!("taskmasterd must be run as root");
-		return;
+	// Started in place of a program with sockets
+	if sockets::is_shim() {
+		sockets::exec_program();
 	}
 
-	// Allow the taskmasterctl to connect to the socket
-	unsafe { libc::umask(0o755) };
+	// Re-executed by daemon upgrade, already daemonized and de-escalated
+	let upgrade = state::inherited();
+
+	if upgrade.is_none() {
+		if unsafe { libc::getuid() } != 0 {
+			println!("taskmasterd must be run as root");
+			return;
+		}
+
+		// Allow the taskmasterctl to connect to the socket
+		unsafe { libc::umask(0o755) };
 
-	let stdout = File::create("/tmp/taskmasterd.out").unwrap();
-	let stderr = File::create("/tmp/taskmasterd.err").unwrap();
-	let daemonize = Daemonize::new()
-		.user(std::env::var("SUDO_USER").unwrap().as_str())
-		.group("nobody")
-		.stdout(stdout)
-		.stderr(stderr)
-		.pid_file("/tmp/taskmasterd.pid");
+		let stdout = File::create("/tmp/taskmasterd.out").unwrap();
+		let stderr = File::create("/tmp/taskmasterd.err").unwrap();
+		let daemonize = Daemonize::new()
+			.user(std::env::var("SUDO_USER").unwrap().as_str())
+			.group("nobody")
+			.stdout(stdout)
+			.stderr(stderr)
+			.pid_file(PID_FILE);
 
-	daemonize.start().expect("Failed to daemonize");
+		daemonize.start().expect("Failed to daemonize");
+	}
+	cloexec_pid_file(PID_FILE);
+	if let Err(err) = orphans::become_subreaper() {
+		eprintln!("Could not become a child subreaper: {err}");
+	}
 
-	let listener = bind("/tmp/taskmasterd.sock").expect("Could not create unix socket");
+	let listener = match &upgrade {
+		Some(upgrade) => unsafe { UnixListener::from_raw_fd(upgrade.listener) },
+		None => bind("/tmp/taskmasterd.sock").expect("Could not create unix socket"),
+	};
+	LISTENER.store(listener.as_raw_fd(), Ordering::Relaxed);
 
 	println!("Starting taskmasterd...");
 
+	match upgrade {
+		Some(upgrade) => {
+			sockets::inherit(upgrade.sockets.into_iter().map(|(address, fd)| (address, unsafe { OwnedFd::from_raw_fd(fd) })).collect());
+			TASKS.lock().unwrap().restore(upgrade.state);
+			let mut client = unsafe { UnixStream::from_raw_fd(upgrade.client) };
+			let _ = write_frame(&mut client, &TaskmasterDaemonResult::Success);
+			println!("Upgraded");
+		}
+		// Processes of a daemon that did not stop cleanly
+		None => if let Some(state) = state::load() {
+			TASKS.lock().unwrap().restore(state);
+		}
+	}
+
 	// Health loop
 	{
 		let tasks = TASKS.clone();
 		thread::spawn(move || {
 			println!("Starting health check loop...");
 
+			let mut saved = None;
 			loop {
-				tasks.lock().unwrap().health_check();
-				thread::sleep(Duration::from_nanos(500_000));
+				let state = {
+					let mut tasks = tasks.lock().unwrap();
+					tasks.health_check();
+					tasks.saved_state()
+				};
+				if saved.as_ref() != Some(&state) {
+					state::save(&state);
+					saved = Some(state);
+				}
+				// Woken up early when a process exits
+				handle::wait_for_exit(HEALTH_INTERVAL);
 			}
 		});
 	}
 
 	// Signal handling 
 	unsafe {
-		libc::signal(libc::SIGHUP, handler as usize);
+		libc::signal(libc::SIGHUP, handler as *const () as usize);
 	}
 
 	println!("Starting listener loop...");
 	for stream in listener.incoming() {
 		match stream {
-			Ok(mut stream) => {
+			Ok(stream) => {
 				let tasks = TASKS.clone();
-				thread::spawn(move || {
-					loop {
-						if let Ok(request) = bincode::deserialize_from::<&UnixStream, TaskmasterDaemonRequest>(&stream) {
-							println!("read {:?}", request);
-
-							let response = handle_client_request(
-								&mut tasks.lock().unwrap(),
-								request
-							);
-
-							bincode::serialize_into(&mut stream, &response).unwrap();
-							stream.flush().unwrap();
-						} else {
-							if let Err(e) = stream.shutdown(std::net::Shutdown::Both) {
-								eprintln!("Failed to shutdown stream: {}", e);
-							}
-							break ;
-						}
-					}
-				});
+				thread::spawn(move || handle_client(stream, tasks));
 			}
 			Err(err) => {
 				eprintln!("Failed to connect: {}", err);
@@ -672,3 +2116,203 @@ fn main() {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::{collections::HashSet, os::unix::process::CommandExt, process::Command, time::{Duration, Instant}};
+
+	use yaml_rust::{Yaml, YamlLoader};
+
+	use super::{orphans, parse_size, procfs, rolling_timeout, ExitStatus, ProcessHandle, Task, TaskFile};
+
+	fn yaml(text: &str) -> Yaml {
+		YamlLoader::load_from_str(text).unwrap().remove(0)
+	}
+
+	// A config file written for the test
+	fn load(name: &str, config: &str) -> Result<TaskFile, String> {
+		let path = std::env::temp_dir().join(format!("taskmasterd-test-{}-{name}.yaml", std::process::id()));
+		std::fs::write(&path, config).unwrap();
+		let file = TaskFile::from_yaml(path.to_str().unwrap());
+		std::fs::remove_file(&path).unwrap();
+		file
+	}
+
+	fn task(name: &str, config: &str) -> Task {
+		load(name, config).unwrap().tasks.remove(name).unwrap()
+	}
+
+	// Signal 0 to the test itself always succeeds, a process that never existed never does
+	fn alive() -> ProcessHandle {
+		let pid = std::process::id();
+		ProcessHandle::Adopted{pid, starttime: procfs::stat(pid).unwrap().starttime, pidfd: None}
+	}
+
+	fn gone() -> ProcessHandle {
+		ProcessHandle::Adopted{pid: u32::MAX, starttime: 0, pidfd: None}
+	}
+
+	#[test]
+	fn sizes_in_bytes_or_with_a_unit() {
+		assert_eq!(parse_size(&Yaml::BadValue), Ok(None));
+		assert_eq!(parse_size(&yaml("4096")), Ok(Some(4096)));
+		assert_eq!(parse_size(&yaml("512B")), Ok(Some(512)));
+		assert_eq!(parse_size(&yaml("64K")), Ok(Some(64 << 10)));
+		assert_eq!(parse_size(&yaml("512 MB")), Ok(Some(512 << 20)));
+		assert_eq!(parse_size(&yaml("2G")), Ok(Some(2 << 30)));
+	}
+
+	#[test]
+	fn invalid_sizes_are_rejected() {
+		assert!(parse_size(&yaml("-1")).is_err());
+		assert!(parse_size(&yaml("12T")).is_err());
+		assert!(parse_size(&yaml("M")).is_err());
+		assert!(parse_size(&yaml("[1]")).is_err());
+		assert!(parse_size(&yaml("99999999999999999999G")).is_err());
+	}
+
+	#[test]
+	fn signal_out_of_range_process() {
+		let mut task = task("sig", "programs:\n  sig:\n    cmd: sleep 60\n    numprocs: 2\n");
+		task.grow();
+		assert_eq!(task.signal(Some(2), 0), Err("sig has no process 2".to_owned()));
+		assert_eq!(task.signal(Some(1), 0), Err("sig is not running".to_owned()));
+	}
+
+	#[test]
+	fn signal_reports_every_failure() {
+		let mut task = task("sig", "programs:\n  sig:\n    cmd: sleep 60\n    numprocs: 3\n");
+		task.grow();
+		task.processes[0].process = Some(gone());
+		task.processes[1].process = Some(alive());
+		task.processes[2].process = Some(gone());
+
+		let pid = std::process::id();
+		let err = task.signal(None, 0).unwrap_err();
+		assert!(err.starts_with(&format!("Signaled sig[1] (pid {pid}), could not signal sig[0]: ")), "{err}");
+		assert!(err.contains(", sig[2]: "), "{err}");
+		assert_eq!(task.signal(Some(1), 0), Ok(format!("Signaled sig[1] (pid {pid})")));
+		assert!(task.signal(Some(0), 0).unwrap_err().starts_with("Could not signal sig[0]: "));
+	}
+
+	#[test]
+	fn killed_process_is_not_an_orphan() {
+		let mut task = task("kill", "programs:\n  kill:\n    cmd: sleep 60\n");
+		task.grow();
+		let (process, opts) = task.process_mut(0).unwrap();
+		// Out of our process group, like every process the daemon spawns
+		let child = Command::new("sleep").arg("60").process_group(0).spawn().unwrap();
+		let pid = child.id();
+		process.process = Some(ProcessHandle::spawned(child));
+
+		process.kill(opts);
+		let orphans = orphans::Reaper::new().scan(&HashSet::new()).unwrap();
+		assert!(orphans.iter().all(|orphan| orphan.pid != pid));
+		assert!(procfs::stat(pid).is_err());
+	}
+
+	#[test]
+	fn negative_numprocs_start_is_rejected() {
+		let file = load("start", "programs:\n  start:\n    cmd: sleep 60\n    numprocs_start: -1\n");
+		assert_eq!(file.err(), Some("numprocs_start need to be a positive number".to_owned()));
+		assert_eq!(task("start", "programs:\n  start:\n    cmd: sleep 60\n    numprocs_start: 3\n").options.numprocs_start, 3);
+	}
+
+	#[test]
+	fn rolled_once_back_with_another_pid() {
+		let mut task = task("roll", "programs:\n  roll:\n    cmd: sleep 60\n    starttime: 2\n");
+		task.grow();
+		let (process, opts) = task.process_mut(0).unwrap();
+		let long_ago = Instant::now() - Duration::from_secs(5);
+
+		process.current_status = ExitStatus::Running{since: long_ago, pid: 10};
+		assert_eq!(process.rolled(Some(10), opts, false), None);
+		assert_eq!(process.rolled(Some(9), opts, false), Some(Ok(())));
+		assert_eq!(process.rolled(None, opts, true), Some(Ok(())));
+
+		// Past its starttime a starting process counts, unless it has to be ready
+		process.current_status = ExitStatus::Starting{since: long_ago, pid: 11};
+		assert_eq!(process.rolled(Some(10), opts, false), Some(Ok(())));
+		assert_eq!(process.rolled(Some(10), opts, true), None);
+		process.current_status = ExitStatus::Starting{since: Instant::now(), pid: 11};
+		assert_eq!(process.rolled(Some(10), opts, false), None);
+
+		// The old process is still on its way out
+		for status in [ExitStatus::PreStop{at: long_ago}, ExitStatus::Stopping{at: long_ago}, ExitStatus::PreStart{at: long_ago}] {
+			process.current_status = status;
+			assert_eq!(process.rolled(Some(10), opts, false), None);
+		}
+	}
+
+	#[test]
+	fn rolling_restart_aborts_on_a_failed_process() {
+		let mut task = task("roll", "programs:\n  roll:\n    cmd: sleep 60\n");
+		task.grow();
+		let (process, opts) = task.process_mut(0).unwrap();
+		let now = Instant::now();
+
+		process.current_status = ExitStatus::Exited{at: now, code: 3};
+		assert_eq!(process.rolled(Some(10), opts, false), Some(Err("roll[0] exited with code 3".to_owned())));
+		process.current_status = ExitStatus::LaunchFailed{at: now, err: "No such file".to_owned()};
+		assert_eq!(process.rolled(Some(10), opts, false), Some(Err("roll[0] could not start: No such file".to_owned())));
+		process.current_status = ExitStatus::Killed{at: now};
+		assert_eq!(process.rolled(Some(10), opts, false), Some(Err("roll[0] stopped".to_owned())));
+
+		// A failed readiness probe only matters when waiting for it
+		process.current_status = ExitStatus::Starting{since: now, pid: 11};
+		process.not_ready = Some("never became ready".to_owned());
+		assert_eq!(process.rolled(Some(10), opts, true), Some(Err("roll[0] never became ready".to_owned())));
+		assert_eq!(process.rolled(Some(10), opts, false), Some(Ok(())));
+	}
+
+	#[test]
+	fn rolling_timeout_covers_every_step() {
+		let task = task("roll", "programs:\n  roll:\n    cmd: sleep 60\n    starttime: 3\n    stoptime: 2\n    hook_timeout: 5\n    pre_start: \"true\"\n    pre_stop: \"true\"\n    readiness:\n      exec: \"true\"\n      interval: 1\n      timeout: 1\n");
+		assert_eq!(rolling_timeout(&task.options, false), Duration::from_secs(5 * 2 + 2 + 3 + 10));
+		assert_eq!(rolling_timeout(&task.options, true), Duration::from_secs(5 * 2 + 2 + 3 + 2 * 3 + 10));
+	}
+
+	#[test]
+	fn scaled_down_processes_get_their_index_back() {
+		let mut task = task("grow", "programs:\n  grow:\n    cmd: sleep 60\n    numprocs: 3\n");
+		task.grow();
+		// Still stopping once scaled down, so kept as retiring
+		let mut pids = Vec::new();
+		for process in &mut task.processes[1..] {
+			let child = Command::new("sleep").arg("60").process_group(0).spawn().unwrap();
+			pids.push(child.id());
+			process.process = Some(ProcessHandle::spawned(child));
+		}
+		let pid = |process: &super::Process| process.process.as_ref().map(ProcessHandle::id);
+
+		assert_eq!(task.scale(Some(1)), "Scaled grow to 1 processes (3 in the config)");
+		assert_eq!(task.processes.len(), 1);
+		assert_eq!(task.retiring.iter().map(|process| process.index).collect::<Vec<_>>(), vec![1, 2]);
+
+		task.scale(Some(2));
+		assert_eq!(pid(&task.processes[1]), Some(pids[0]));
+		assert_eq!(task.retiring.iter().map(|process| process.index).collect::<Vec<_>>(), vec![2]);
+
+		assert_eq!(task.scale(None), "Scaled grow back to the 3 processes of the config");
+		assert_eq!(task.scaled_from, None);
+		assert_eq!(task.processes.iter().map(pid).collect::<Vec<_>>(), vec![None, Some(pids[0]), Some(pids[1])]);
+		assert!(task.retiring.is_empty());
+
+		for process in &mut task.processes {
+			process.kill(&task.options);
+		}
+	}
+
+	#[test]
+	fn scaling_a_stopped_task_starts_nothing() {
+		let mut task = task("grow", "programs:\n  grow:\n    cmd: sleep 60\n");
+		task.grow();
+		task.scale(Some(3));
+		assert_eq!(task.processes.iter().map(|process| process.index).collect::<Vec<_>>(), vec![0, 1, 2]);
+		assert!(task.processes.iter().all(|process| process.process.is_none()));
+		assert_eq!(task.scale(Some(2)), "Scaled grow to 2 processes (1 in the config)");
+		// Nothing running, nothing left to wait for
+		task.health_check();
+		assert!(task.retiring.is_empty());
+	}
+}
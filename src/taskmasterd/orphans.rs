@@ -0,0 +1,60 @@
+use std::{collections::HashSet, io, time::{Duration, Instant}};
+
+use crate::procfs;
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+// Descendants whose parent exited are re-parented to us instead of init
+pub fn become_subreaper() -> io::Result<()> {
+	if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+pub struct Orphan {
+	pub pid: u32,
+	pub pgrp: u32,
+	pub state: char,
+}
+
+impl Orphan {
+	// The exit code, or the signal that killed it, of a zombie orphan
+	pub fn reap(&self) -> String {
+		let mut status = 0;
+		if unsafe { libc::waitpid(self.pid as libc::pid_t, &mut status, libc::WNOHANG) } <= 0 {
+			return "unknown status".to_owned();
+		}
+		if libc::WIFSIGNALED(status) {
+			return format!("signal {}", libc::WTERMSIG(status));
+		}
+		format!("code {}", libc::WEXITSTATUS(status))
+	}
+}
+
+pub struct Reaper {
+	next_at: Instant,
+}
+
+impl Reaper {
+	pub fn new() -> Reaper {
+		Reaper { next_at: Instant::now() }
+	}
+
+	// Our children that we did not spawn, checked once per interval
+	// Those in our own process group are probes, waited for by the thread that spawned them
+	pub fn scan(&mut self, spawned: &HashSet<u32>) -> Option<Vec<Orphan>> {
+		if Instant::now() < self.next_at {
+			return None;
+		}
+		self.next_at = Instant::now() + SCAN_INTERVAL;
+
+		let pgrp = unsafe { libc::getpgrp() } as u32;
+		let children = procfs::children().remove(&std::process::id()).unwrap_or_default();
+		Some(children.into_iter()
+			.filter(|pid| !spawned.contains(pid))
+			.filter_map(|pid| procfs::stat(pid).ok().map(|stat| Orphan { pid, pgrp: stat.pgrp, state: stat.state }))
+			.filter(|orphan| orphan.pgrp != pgrp)
+			.collect())
+	}
+}
@@ -0,0 +1,79 @@
+use std::{
+	fs::File,
+	io::{ErrorKind, Read, Write},
+	os::fd::{AsRawFd, OwnedFd, RawFd},
+	sync::mpsc::{self, Receiver, SyncSender},
+};
+
+use taskmastersocket::LogStream;
+
+// Chunks an attached client can fall behind by before it is dropped
+const CLIENT_BACKLOG: usize = 256;
+// Read at most this much of a pipe per health check, a chatty process does not hold the daemon
+const CHUNK: usize = 16 * 1024;
+const CHUNKS_PER_POLL: usize = 64;
+
+pub type Chunk = (LogStream, Vec<u8>);
+
+struct Pipe {
+	stream: LogStream,
+	pipe: File,
+	log: Option<File>,
+}
+
+// The stdout and stderr of a process with a stdin pipe go through the daemon,
+// copied to their log files and to the attached clients
+#[derive(Default)]
+pub struct Output {
+	pipes: Vec<Pipe>,
+	clients: Vec<SyncSender<Chunk>>,
+}
+
+impl Output {
+	// At each launch, or once handed over by an upgrade
+	pub fn set(&mut self, stream: LogStream, pipe: Option<OwnedFd>, log: Option<File>) {
+		self.pipes.retain(|pipe| pipe.stream != stream);
+		if let Some(pipe) = pipe {
+			unsafe {
+				let flags = libc::fcntl(pipe.as_raw_fd(), libc::F_GETFL);
+				libc::fcntl(pipe.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK);
+			}
+			self.pipes.push(Pipe { stream, pipe: File::from(pipe), log });
+		}
+	}
+
+	// For an upgrade, the new daemon keeps reading them
+	pub fn fd(&self, stream: LogStream) -> Option<RawFd> {
+		self.pipes.iter().find(|pipe| pipe.stream == stream).map(|pipe| pipe.pipe.as_raw_fd())
+	}
+
+	// Closed once the client is gone or too slow
+	pub fn attach(&mut self) -> Receiver<Chunk> {
+		let (sender, receiver) = mpsc::sync_channel(CLIENT_BACKLOG);
+		self.clients.push(sender);
+		receiver
+	}
+
+	// Until the end of the pipes, what the process wrote before exiting is still copied
+	pub fn poll(&mut self) {
+		let clients = &mut self.clients;
+		let mut chunk = vec![0u8; CHUNK];
+
+		self.pipes.retain_mut(|pipe| {
+			for _ in 0..CHUNKS_PER_POLL {
+				match pipe.pipe.read(&mut chunk) {
+					Ok(0) => return false,
+					Ok(n) => {
+						if let Some(log) = &mut pipe.log {
+							let _ = log.write_all(&chunk[..n]);
+						}
+						clients.retain(|client| client.try_send((pipe.stream, chunk[..n].to_vec())).is_ok());
+					}
+					Err(err) if err.kind() == ErrorKind::Interrupted => {}
+					Err(err) => return err.kind() == ErrorKind::WouldBlock,
+				}
+			}
+			true
+		});
+	}
+}
@@ -0,0 +1,256 @@
+use std::{
+	io::{Read, Write},
+	net::{TcpStream, ToSocketAddrs},
+	os::unix::net::UnixStream,
+	process::{Command, Stdio},
+	sync::mpsc::{self, Receiver},
+	thread,
+	time::{Duration, Instant},
+};
+
+use yaml_rust::Yaml;
+
+use crate::TaskOptions;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum ProbeCheck {
+	Exec(String),                   // succeeds when the command exits with 0
+	Tcp(String),                    // succeeds when host:port accepts a connection
+	Unix(String),                   // succeeds when the socket accepts a connection
+	Http{port: u16, path: String},  // succeeds on a 2xx or 3xx answer from localhost
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct ProbeOptions {
+	check: ProbeCheck,
+	interval_sec: u64,
+	timeout_sec: u64,
+	failure_threshold: u32,
+}
+
+impl ProbeOptions {
+	pub fn from_yaml(yaml: &Yaml) -> Result<Option<ProbeOptions>, String> {
+		if yaml.is_badvalue() {
+			return Ok(None);
+		}
+
+		let check = if let Some(cmd) = yaml["exec"].as_str() {
+			ProbeCheck::Exec(cmd.to_owned())
+		} else if let Some(addr) = yaml["tcp"].as_str() {
+			ProbeCheck::Tcp(addr.to_owned())
+		} else if let Some(path) = yaml["unix"].as_str() {
+			ProbeCheck::Unix(path.to_owned())
+		} else if !yaml["http"].is_badvalue() {
+			let http = &yaml["http"];
+			ProbeCheck::Http {
+				port: http["port"].as_i64().and_then(|p| u16::try_from(p).ok())
+					.ok_or("http probe port is required and need to be a port number")?,
+				path: http["path"].as_str().unwrap_or("/").to_owned(),
+			}
+		} else {
+			return Err("A probe needs one of exec, tcp, unix or http".to_owned());
+		};
+
+		let positive = |key: &str, default: i64| match &yaml[key] {
+			Yaml::BadValue => Ok(default),
+			value => value.as_i64().filter(|n| *n > 0).ok_or(format!("Probe {key} needs to be a positive int")),
+		};
+
+		Ok(Some(ProbeOptions {
+			check,
+			interval_sec: positive("interval", 10)? as u64,
+			timeout_sec: positive("timeout", 1)? as u64,
+			failure_threshold: u32::try_from(positive("failure_threshold", 3)?).map_err(|_| "Probe failure_threshold is too large")?,
+		}))
+	}
+
+	pub fn failure_threshold(&self) -> u32 {
+		self.failure_threshold
+	}
+
+	// Longest it takes to fail failure_threshold checks in a row
+	pub fn failing_sec(&self) -> u64 {
+		(self.interval_sec + self.timeout_sec) * self.failure_threshold as u64
+	}
+}
+
+fn check_exec(cmd: &str, opts: &TaskOptions, timeout: Duration) -> Result<(), String> {
+	let mut command = Command::new("/bin/sh");
+
+	command.arg("-c").arg(cmd)
+		.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null())
+		.envs(&opts.env);
+	if let Some(workingdir) = &opts.workingdir {
+		command.current_dir(workingdir);
+	}
+
+	let mut child = command.spawn().map_err(|err| err.to_string())?;
+	let started = Instant::now();
+	loop {
+		match child.try_wait().map_err(|err| err.to_string())? {
+			Some(status) if status.success() => return Ok(()),
+			Some(status) => return Err(format!("{cmd} failed with {status}")),
+			None if started.elapsed() >= timeout => {
+				let _ = child.kill();
+				let _ = child.wait();
+				return Err(format!("{cmd} timed out"));
+			}
+			None => thread::sleep(Duration::from_millis(10)),
+		}
+	}
+}
+
+fn check_tcp(addr: &str, timeout: Duration) -> Result<TcpStream, String> {
+	let addrs = addr.to_socket_addrs().map_err(|err| format!("{addr}: {err}"))?;
+	let mut last_err = format!("{addr}: no address");
+
+	for addr in addrs {
+		match TcpStream::connect_timeout(&addr, timeout) {
+			Ok(stream) => return Ok(stream),
+			Err(err) => last_err = format!("{addr}: {err}"),
+		}
+	}
+	Err(last_err)
+}
+
+fn check_http(port: u16, path: &str, timeout: Duration) -> Result<(), String> {
+	let mut stream = check_tcp(&format!("127.0.0.1:{port}"), timeout)?;
+	let io_err = |err: std::io::Error| format!("GET {path}: {err}");
+
+	stream.set_read_timeout(Some(timeout)).map_err(io_err)?;
+	stream.set_write_timeout(Some(timeout)).map_err(io_err)?;
+	stream.write_all(format!("GET {path} HTTP/1.0\r\nHost: localhost\r\nConnection: close\r\n\r\n").as_bytes())
+		.map_err(io_err)?;
+
+	// Only the status line matters
+	let mut head = [0u8; 32];
+	let mut len = 0;
+	while len < head.len() {
+		match stream.read(&mut head[len..]).map_err(io_err)? {
+			0 => break,
+			n => len += n,
+		}
+	}
+	let code = String::from_utf8_lossy(&head[..len]).split_whitespace().nth(1)
+		.and_then(|code| code.parse::<u16>().ok())
+		.ok_or(format!("GET {path}: invalid answer"))?;
+
+	if (200..400).contains(&code) {
+		Ok(())
+	} else {
+		Err(format!("GET {path}: status {code}"))
+	}
+}
+
+fn check(probe: &ProbeOptions, opts: &TaskOptions) -> Result<(), String> {
+	let timeout = Duration::from_secs(probe.timeout_sec);
+
+	match &probe.check {
+		ProbeCheck::Exec(cmd) => check_exec(cmd, opts, timeout),
+		ProbeCheck::Tcp(addr) => check_tcp(addr, timeout).map(|_| ()),
+		ProbeCheck::Unix(path) => UnixStream::connect(path).map(|_| ()).map_err(|err| format!("{path}: {err}")),
+		ProbeCheck::Http{port, path} => check_http(*port, path, timeout),
+	}
+}
+
+// Runtime state of a probe for one process, checks run in their own thread
+pub struct Probe {
+	opts: ProbeOptions,
+	next_at: Instant,
+	pending: Option<Receiver<Result<(), String>>>,
+	pub failures: u32,
+	pub last_error: Option<String>,
+}
+
+impl Probe {
+	pub fn new(opts: &ProbeOptions) -> Probe {
+		Probe {
+			opts: opts.clone(),
+			next_at: Instant::now(),
+			pending: None,
+			failures: 0,
+			last_error: None,
+		}
+	}
+
+	// A check is running in its thread
+	pub fn checking(&self) -> bool {
+		self.pending.is_some()
+	}
+
+	// Result of the check that just completed, if any
+	pub fn poll(&mut self, task: &TaskOptions) -> Option<Result<(), String>> {
+		if let Some(pending) = &self.pending {
+			let result = match pending.try_recv() {
+				Ok(result) => result,
+				Err(mpsc::TryRecvError::Empty) => return None,
+				Err(mpsc::TryRecvError::Disconnected) => Err("probe crashed".to_owned()),
+			};
+
+			self.pending = None;
+			self.next_at = Instant::now() + Duration::from_secs(self.opts.interval_sec);
+			match &result {
+				Ok(()) => {
+					self.failures = 0;
+					self.last_error = None;
+				}
+				Err(err) => {
+					self.failures += 1;
+					self.last_error = Some(err.clone());
+				}
+			}
+			return Some(result);
+		}
+
+		if Instant::now() >= self.next_at {
+			let (sender, receiver) = mpsc::channel();
+			let (probe, task) = (self.opts.clone(), task.clone());
+
+			thread::spawn(move || {
+				let _ = sender.send(check(&probe, &task));
+			});
+			self.pending = Some(receiver);
+		}
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use yaml_rust::YamlLoader;
+
+	use super::{ProbeCheck, ProbeOptions};
+
+	fn options(text: &str) -> Result<Option<ProbeOptions>, String> {
+		ProbeOptions::from_yaml(&YamlLoader::load_from_str(text).unwrap().remove(0)["probe"])
+	}
+
+	#[test]
+	fn checks() {
+		let exec = options("probe:\n  exec: test -e /tmp/ready").unwrap().unwrap();
+		assert_eq!(exec.check, ProbeCheck::Exec("test -e /tmp/ready".to_owned()));
+		assert_eq!((exec.interval_sec, exec.timeout_sec, exec.failure_threshold), (10, 1, 3));
+
+		let http = options("probe:\n  http:\n    port: 8080\n  interval: 2\n  timeout: 5\n  failure_threshold: 1").unwrap().unwrap();
+		assert_eq!(http.check, ProbeCheck::Http{port: 8080, path: "/".to_owned()});
+		assert_eq!((http.interval_sec, http.timeout_sec, http.failure_threshold), (2, 5, 1));
+		assert_eq!(http.failing_sec(), 7);
+
+		assert_eq!(options("probe:\n  tcp: 127.0.0.1:80").unwrap().unwrap().check, ProbeCheck::Tcp("127.0.0.1:80".to_owned()));
+		assert_eq!(options("probe:\n  unix: /run/app.sock").unwrap().unwrap().check, ProbeCheck::Unix("/run/app.sock".to_owned()));
+		assert_eq!(options("other: 1").unwrap(), None);
+	}
+
+	#[test]
+	fn invalid_options_are_rejected() {
+		assert!(options("probe:\n  interval: 1").is_err());
+		assert!(options("probe:\n  http:\n    port: 70000").is_err());
+		for key in ["interval", "timeout", "failure_threshold"] {
+			for value in ["0", "-1", "ten", "1.5"] {
+				let err = options(&format!("probe:\n  exec: 'true'\n  {key}: {value}")).unwrap_err();
+				assert_eq!(err, format!("Probe {key} needs to be a positive int"));
+			}
+		}
+		assert!(options("probe:\n  exec: 'true'\n  failure_threshold: 5000000000").is_err());
+	}
+}
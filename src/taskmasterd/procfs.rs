@@ -0,0 +1,89 @@
+use std::{collections::HashMap, fs, io};
+
+// Fields of /proc/<pid>/stat, times are in clock ticks
+pub struct ProcStat {
+	pub comm: String,
+	pub state: char,
+	pub ppid: u32,
+	pub pgrp: u32,
+	pub utime: u64,
+	pub stime: u64,
+	pub starttime: u64,
+	pub rss_bytes: u64,
+}
+
+pub fn clock_ticks() -> u64 {
+	unsafe { libc::sysconf(libc::_SC_CLK_TCK) as u64 }
+}
+
+fn page_size() -> u64 {
+	unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+pub fn stat(pid: u32) -> io::Result<ProcStat> {
+	let content = fs::read_to_string(format!("/proc/{pid}/stat"))?;
+	let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid /proc/{pid}/stat"));
+
+	// The command name is between parentheses and can contain anything
+	let (head, tail) = content.rsplit_once(')').ok_or_else(invalid)?;
+	let comm = head.split_once('(').ok_or_else(invalid)?.1.to_owned();
+	let fields: Vec<&str> = tail.split_whitespace().collect();
+	let field = |n: usize| -> io::Result<u64> {
+		// fields[0] is the third field of the file
+		fields.get(n - 3).and_then(|f| f.parse().ok()).ok_or_else(invalid)
+	};
+
+	Ok(ProcStat {
+		comm,
+		state: fields.first().and_then(|s| s.chars().next()).ok_or_else(invalid)?,
+		ppid: field(4)? as u32,
+		pgrp: field(5)? as u32,
+		utime: field(14)?,
+		stime: field(15)?,
+		starttime: field(22)?,
+		rss_bytes: field(24)? * page_size(),
+	})
+}
+
+// Fields of /proc/<pid>/status, the values are in kB or counts
+pub fn status(pid: u32) -> io::Result<HashMap<String, u64>> {
+	let content = fs::read_to_string(format!("/proc/{pid}/status"))?;
+
+	Ok(content.lines().filter_map(|line| {
+		let (key, value) = line.split_once(':')?;
+		Some((key.to_owned(), value.split_whitespace().next()?.parse().ok()?))
+	}).collect())
+}
+
+pub fn open_fds(pid: u32) -> io::Result<usize> {
+	Ok(fs::read_dir(format!("/proc/{pid}/fd"))?.count())
+}
+
+pub fn cmdline(pid: u32) -> io::Result<String> {
+	let content = fs::read(format!("/proc/{pid}/cmdline"))?;
+	Ok(String::from_utf8_lossy(&content).trim_end_matches('\0').replace('\0', " "))
+}
+
+// Seconds since boot, to turn a starttime into an age
+pub fn uptime() -> io::Result<f64> {
+	fs::read_to_string("/proc/uptime")?
+		.split_whitespace().next()
+		.and_then(|s| s.parse().ok())
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid /proc/uptime"))
+}
+
+// Children of every process on the system, by parent pid
+pub fn children() -> HashMap<u32, Vec<u32>> {
+	let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+
+	for entry in fs::read_dir("/proc").into_iter().flatten().flatten() {
+		let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else { continue };
+		if let Ok(stat) = stat(pid) {
+			children.entry(stat.ppid).or_default().push(pid);
+		}
+	}
+	for pids in children.values_mut() {
+		pids.sort();
+	}
+	children
+}
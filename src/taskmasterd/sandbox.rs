@@ -0,0 +1,369 @@
+use std::{ffi::CString, io, os::unix::process::CommandExt, path::Path, process::Command, sync::atomic::{AtomicI32, Ordering}};
+
+use yaml_rust::Yaml;
+
+const CAPABILITIES: [&str; 41] = [
+	"chown", "dac_override", "dac_read_search", "fowner", "fsetid", "kill", "setgid", "setuid",
+	"setpcap", "linux_immutable", "net_bind_service", "net_broadcast", "net_admin", "net_raw",
+	"ipc_lock", "ipc_owner", "sys_module", "sys_rawio", "sys_chroot", "sys_ptrace", "sys_pacct",
+	"sys_admin", "sys_boot", "sys_nice", "sys_resource", "sys_time", "sys_tty_config", "mknod",
+	"lease", "audit_write", "audit_control", "setfcap", "mac_override", "mac_admin", "syslog",
+	"wake_alarm", "block_suspend", "audit_read", "perfmon", "bpf", "checkpoint_restore",
+];
+
+// What the child was doing when it failed, sent back to the daemon through a pipe
+const STEPS: [&str; 10] = [
+	"create the namespaces",
+	"fork into the pid namespace",
+	"make the mounts private",
+	"set the hostname",
+	"bring up the loopback interface",
+	"bind mount a writable path",
+	"remount the root read-only",
+	"mount /proc",
+	"chroot",
+	"drop capabilities",
+];
+const STEP_NO_NEW_PRIVS: u8 = STEPS.len() as u8;
+
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct SandboxOptions {
+	namespaces: libc::c_int, // CLONE_NEW* flags
+	hostname: Option<String>,
+	no_new_privs: bool,
+	read_only_root: bool,
+	writable_paths: Vec<String>,
+	chroot: Option<String>,
+	drop_capabilities: Vec<u32>,
+}
+
+fn last_capability() -> u32 {
+	std::fs::read_to_string("/proc/sys/kernel/cap_last_cap").ok()
+		.and_then(|last| last.trim().parse().ok())
+		.unwrap_or(CAPABILITIES.len() as u32 - 1)
+}
+
+fn strings(yaml: &Yaml, key: &str) -> Result<Vec<String>, String> {
+	match &yaml[key] {
+		Yaml::BadValue => Ok(Vec::new()),
+		list => list.as_vec()
+			.and_then(|list| list.iter().map(|s| s.as_str().map(|s| s.to_owned())).collect())
+			.ok_or(format!("sandbox.{key} need to be a list of strings")),
+	}
+}
+
+impl SandboxOptions {
+	pub fn from_yaml(yaml: &Yaml) -> Result<SandboxOptions, String> {
+		if yaml.is_badvalue() {
+			return Ok(SandboxOptions::default());
+		}
+
+		let mut namespaces = 0;
+		for namespace in strings(yaml, "namespaces")? {
+			namespaces |= match namespace.as_str() {
+				"pid" => libc::CLONE_NEWPID,
+				"mount" => libc::CLONE_NEWNS,
+				"network" => libc::CLONE_NEWNET,
+				"uts" => libc::CLONE_NEWUTS,
+				_ => return Err(format!("Unknown namespace {namespace:?}, expected pid, mount, network or uts")),
+			};
+		}
+
+		let read_only_root = yaml["read_only_root"].as_bool().unwrap_or(false);
+		let writable_paths = strings(yaml, "writable_paths")?;
+		if !writable_paths.is_empty() && !read_only_root {
+			return Err("sandbox.writable_paths only make sense with read_only_root".to_owned());
+		}
+		for path in &writable_paths {
+			if !Path::new(path).is_absolute() || !Path::new(path).exists() {
+				return Err(format!("sandbox.writable_paths: {path} need to be an existing absolute path"));
+			}
+		}
+		// Remounting outside of a private mount namespace would change the whole system
+		if read_only_root {
+			namespaces |= libc::CLONE_NEWNS;
+		}
+
+		let chroot = yaml["chroot"].as_str().map(|s| s.to_owned());
+		if let Some(chroot) = &chroot {
+			if !Path::new(chroot).is_dir() {
+				return Err(format!("sandbox.chroot: {chroot} is not a directory"));
+			}
+		}
+
+		let last = last_capability();
+		let mut drop_capabilities = Vec::new();
+		for name in strings(yaml, "drop_capabilities")? {
+			let name = name.to_lowercase();
+			if name == "all" {
+				drop_capabilities = (0..=last).collect();
+				break;
+			}
+			let cap = CAPABILITIES.iter().position(|cap| *cap == name.trim_start_matches("cap_"))
+				.ok_or(format!("Unknown capability {name:?}"))? as u32;
+			if cap > last {
+				return Err(format!("Capability {name} is not supported by this kernel"));
+			}
+			drop_capabilities.push(cap);
+		}
+
+		Ok(SandboxOptions {
+			namespaces,
+			hostname: yaml["hostname"].as_str().map(|s| s.to_owned()),
+			no_new_privs: yaml["no_new_privs"].as_bool().unwrap_or(false),
+			read_only_root,
+			writable_paths,
+			chroot,
+			drop_capabilities,
+		})
+	}
+
+	// A fresh /proc only shows the pid namespace once it is mounted in a mount namespace of its own
+	fn mounts_proc(&self) -> bool {
+		self.namespaces & libc::CLONE_NEWPID != 0 && self.namespaces & libc::CLONE_NEWNS != 0
+	}
+
+	// The returned report tells which step failed when the spawn does
+	pub fn apply(&self, command: &mut Command, program: &str, workingdir: Option<&str>) -> Result<Option<Report>, String> {
+		if *self == SandboxOptions::default() {
+			return Ok(None);
+		}
+
+		let cstring = |s: &str| CString::new(s).map_err(|_| format!("sandbox: invalid path {s:?}"));
+		let opts = self.clone();
+		let mounts_proc = self.mounts_proc();
+		let hostname = self.hostname.clone().unwrap_or(program.to_owned());
+		let writable_paths = self.writable_paths.iter().map(|path| cstring(path)).collect::<Result<Vec<_>, _>>()?;
+		let root = cstring("/")?;
+		let proc_type = cstring("proc")?;
+		let proc_path = cstring(&match &self.chroot {
+			Some(chroot) => format!("{}/proc", chroot.trim_end_matches('/')),
+			None => "/proc".to_owned(),
+		})?;
+		let chroot = self.chroot.as_deref().map(cstring).transpose()?;
+		let chdir = cstring(workingdir.unwrap_or("/"))?;
+		let report = Report::new()?;
+		let report_fd = report.write_fd;
+
+		unsafe {
+			command.pre_exec(move || {
+				let mut step = 0u8;
+				let result = (|| {
+					let check = |ret: libc::c_int| if ret < 0 { Err(io::Error::last_os_error()) } else { Ok(()) };
+					let null = std::ptr::null();
+
+					check(libc::unshare(opts.namespaces))?;
+					step += 1;
+					if opts.namespaces & libc::CLONE_NEWPID != 0 {
+						fork_into_pid_namespace()?;
+					}
+					step += 1;
+					if opts.namespaces & libc::CLONE_NEWNS != 0 {
+						check(libc::mount(null, root.as_ptr(), null, libc::MS_REC | libc::MS_PRIVATE, std::ptr::null()))?;
+					}
+					step += 1;
+					if opts.namespaces & libc::CLONE_NEWUTS != 0 {
+						check(libc::sethostname(hostname.as_ptr() as *const libc::c_char, hostname.len()))?;
+					}
+					step += 1;
+					if opts.namespaces & libc::CLONE_NEWNET != 0 {
+						loopback_up()?;
+					}
+					step += 1;
+					// Writable paths become mounts of their own before the root turns read-only
+					for path in &writable_paths {
+						check(libc::mount(path.as_ptr(), path.as_ptr(), null, libc::MS_BIND | libc::MS_REC, std::ptr::null()))?;
+					}
+					step += 1;
+					if opts.read_only_root {
+						let flags = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY;
+						check(libc::mount(null, root.as_ptr(), null, flags, std::ptr::null()))?;
+					}
+					step += 1;
+					if mounts_proc {
+						let flags = libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC;
+						check(libc::mount(proc_type.as_ptr(), proc_path.as_ptr(), proc_type.as_ptr(), flags, std::ptr::null()))?;
+					}
+					step += 1;
+					if let Some(chroot) = &chroot {
+						check(libc::chroot(chroot.as_ptr()))?;
+						check(libc::chdir(chdir.as_ptr()))?;
+					}
+					step += 1;
+					for cap in &opts.drop_capabilities {
+						check(libc::prctl(libc::PR_CAPBSET_DROP, *cap as libc::c_ulong, 0, 0, 0))?;
+					}
+					step += 1;
+					if opts.no_new_privs {
+						check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+					}
+					Ok(())
+				})();
+
+				if result.is_err() {
+					libc::write(report_fd, &step as *const u8 as *const libc::c_void, 1);
+				}
+				result
+			});
+		}
+		Ok(Some(report))
+	}
+}
+
+// Pipe the child writes its failed step to, closed on exec
+pub struct Report {
+	read_fd: libc::c_int,
+	write_fd: libc::c_int,
+}
+
+impl Report {
+	fn new() -> Result<Report, String> {
+		let mut fds = [0; 2];
+		if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+			return Err(format!("sandbox: {}", io::Error::last_os_error()));
+		}
+		Ok(Report { read_fd: fds[0], write_fd: fds[1] })
+	}
+
+	// Explain a spawn error
+	pub fn explain(&self, err: &io::Error) -> String {
+		let mut step = 0u8;
+		match unsafe { libc::read(self.read_fd, &mut step as *mut u8 as *mut libc::c_void, 1) } {
+			1 if step == STEP_NO_NEW_PRIVS => format!("sandbox: could not set no_new_privs: {err}"),
+			1 => format!("sandbox: could not {}: {err}", STEPS.get(step as usize).unwrap_or(&"set up")),
+			_ => err.to_string(),
+		}
+	}
+}
+
+impl Drop for Report {
+	fn drop(&mut self) {
+		unsafe {
+			libc::close(self.read_fd);
+			libc::close(self.write_fd);
+		}
+	}
+}
+
+fn loopback_up() -> io::Result<()> {
+	unsafe {
+		let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+		if fd < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let mut ifreq: libc::ifreq = std::mem::zeroed();
+		for (dst, src) in ifreq.ifr_name.iter_mut().zip(b"lo\0") {
+			*dst = *src as libc::c_char;
+		}
+		let mut ret = libc::ioctl(fd, libc::SIOCGIFFLAGS, &mut ifreq);
+		if ret == 0 {
+			ifreq.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+			ret = libc::ioctl(fd, libc::SIOCSIFFLAGS, &ifreq);
+		}
+		let err = io::Error::last_os_error();
+		libc::close(fd);
+		if ret < 0 { Err(err) } else { Ok(()) }
+	}
+}
+
+static PID_ONE: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+	unsafe { libc::kill(PID_ONE.load(Ordering::Relaxed), signal) };
+}
+
+// unshare(CLONE_NEWPID) only applies to children, so the program runs in a second fork
+// while this process stays behind to relay signals and pass on its exit status
+unsafe fn fork_into_pid_namespace() -> io::Result<()> {
+	let pid = libc::fork();
+	if pid < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	if pid == 0 {
+		libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+		return Ok(());
+	}
+
+	PID_ONE.store(pid, Ordering::Relaxed);
+	for signal in 1..32 {
+		if ![libc::SIGKILL, libc::SIGSTOP, libc::SIGCHLD].contains(&signal) {
+			libc::signal(signal, forward_signal as *const () as libc::sighandler_t);
+		}
+	}
+	// The exec of the program has to be the last one holding the spawn pipes
+	if libc::syscall(libc::SYS_close_range, 3, u32::MAX, 0) != 0 {
+		for fd in 3..1024 {
+			libc::close(fd);
+		}
+	}
+
+	let mut status = 0;
+	while libc::waitpid(pid, &mut status, 0) < 0 {
+		if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+			libc::_exit(1);
+		}
+	}
+	if libc::WIFEXITED(status) {
+		libc::_exit(libc::WEXITSTATUS(status));
+	}
+	let signal = libc::WTERMSIG(status);
+	libc::signal(signal, libc::SIG_DFL);
+	libc::kill(libc::getpid(), signal);
+	libc::_exit(128 + signal);
+}
+
+#[cfg(test)]
+mod tests {
+	use yaml_rust::YamlLoader;
+
+	use super::SandboxOptions;
+
+	fn options(text: &str) -> Result<SandboxOptions, String> {
+		SandboxOptions::from_yaml(&YamlLoader::load_from_str(text).unwrap().remove(0)["sandbox"])
+	}
+
+	#[test]
+	fn namespaces() {
+		let opts = options("sandbox:\n  namespaces: [pid, network, uts]\n  hostname: box").unwrap();
+		assert_eq!(opts.namespaces, libc::CLONE_NEWPID | libc::CLONE_NEWNET | libc::CLONE_NEWUTS);
+		assert_eq!(opts.hostname.as_deref(), Some("box"));
+		assert_eq!(options("other: 1").unwrap(), SandboxOptions::default());
+
+		assert_eq!(options("sandbox:\n  namespaces: [ipc]").unwrap_err(), "Unknown namespace \"ipc\", expected pid, mount, network or uts");
+		assert!(options("sandbox:\n  namespaces: pid").is_err());
+	}
+
+	// /proc is only mounted with both the pid and the mount namespace, read_only_root implies the latter
+	#[test]
+	fn proc_mount() {
+		assert!(!options("sandbox:\n  namespaces: [pid]").unwrap().mounts_proc());
+		assert!(!options("sandbox:\n  namespaces: [mount]").unwrap().mounts_proc());
+		assert!(options("sandbox:\n  namespaces: [pid, mount]").unwrap().mounts_proc());
+
+		let opts = options("sandbox:\n  namespaces: [pid]\n  read_only_root: true").unwrap();
+		assert_eq!(opts.namespaces, libc::CLONE_NEWPID | libc::CLONE_NEWNS);
+		assert!(opts.mounts_proc());
+	}
+
+	#[test]
+	fn writable_paths() {
+		let opts = options("sandbox:\n  read_only_root: true\n  writable_paths: [/tmp]").unwrap();
+		assert_eq!(opts.writable_paths, ["/tmp"]);
+
+		assert!(options("sandbox:\n  writable_paths: [/tmp]").unwrap_err().contains("only make sense with read_only_root"));
+		assert!(options("sandbox:\n  read_only_root: true\n  writable_paths: [tmp]").is_err());
+		assert!(options("sandbox:\n  read_only_root: true\n  writable_paths: [/nonexistent/taskmaster]").is_err());
+		assert!(options("sandbox:\n  chroot: /nonexistent/taskmaster").is_err());
+	}
+
+	#[test]
+	fn capabilities() {
+		let opts = options("sandbox:\n  drop_capabilities: [CAP_NET_RAW, sys_admin]\n  no_new_privs: true").unwrap();
+		assert_eq!(opts.drop_capabilities, [13, 21]);
+		assert!(opts.no_new_privs);
+		assert!(options("sandbox:\n  drop_capabilities: [all]").unwrap().drop_capabilities.contains(&0));
+
+		assert_eq!(options("sandbox:\n  drop_capabilities: [fly]").unwrap_err(), "Unknown capability \"fly\"");
+		assert!(options("sandbox:\n  drop_capabilities: [1]").is_err());
+	}
+}
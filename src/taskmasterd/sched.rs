@@ -0,0 +1,227 @@
+use std::{ffi::CString, io, os::unix::process::CommandExt, process::Command};
+
+use yaml_rust::Yaml;
+
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum IoClass {
+	Realtime = 1,
+	BestEffort = 2,
+	Idle = 3,
+}
+
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct SchedOptions {
+	nice: Option<i32>,
+	ioprio: Option<(IoClass, i32)>,
+	cpu_affinity: Option<Vec<usize>>,
+	sched_policy: Option<(libc::c_int, i32)>, // policy and its static priority
+	oom_score_adj: Option<i32>,
+}
+
+fn privileged() -> bool {
+	unsafe { libc::geteuid() == 0 }
+}
+
+fn current_oom_score_adj() -> i32 {
+	std::fs::read_to_string("/proc/self/oom_score_adj").ok()
+		.and_then(|adj| adj.trim().parse().ok())
+		.unwrap_or(0)
+}
+
+// "best-effort:4", "realtime:0" or "idle"
+fn parse_ioprio(ioprio: &str) -> Result<(IoClass, i32), String> {
+	let (class, level) = ioprio.split_once(':').unwrap_or((ioprio, "4"));
+	let class = match class {
+		"realtime" | "rt" => IoClass::Realtime,
+		"best-effort" | "be" => IoClass::BestEffort,
+		"idle" => IoClass::Idle,
+		_ => return Err(format!("Invalid ioprio class {class:?}, expected realtime, best-effort or idle")),
+	};
+	let level = level.parse::<i32>().ok().filter(|level| (0..=7).contains(level))
+		.ok_or("ioprio level need to be between 0 and 7")?;
+
+	if class == IoClass::Realtime && !privileged() {
+		return Err("The realtime ioprio class needs root".to_owned());
+	}
+	Ok((class, if class == IoClass::Idle { 0 } else { level }))
+}
+
+// "other", "batch", "idle", "fifo:<priority>" or "rr:<priority>"
+fn parse_sched_policy(policy: &str) -> Result<(libc::c_int, i32), String> {
+	let (name, priority) = policy.split_once(':').map_or((policy, None), |(name, priority)| (name, Some(priority)));
+	let policy = match name {
+		"other" => libc::SCHED_OTHER,
+		"batch" => libc::SCHED_BATCH,
+		"idle" => libc::SCHED_IDLE,
+		"fifo" => libc::SCHED_FIFO,
+		"rr" => libc::SCHED_RR,
+		_ => return Err(format!("Invalid sched_policy {name:?}, expected other, batch, idle, fifo or rr")),
+	};
+
+	let (min, max) = unsafe { (libc::sched_get_priority_min(policy), libc::sched_get_priority_max(policy)) };
+	let priority = match priority {
+		Some(priority) => priority.parse::<i32>().ok().filter(|priority| (min..=max).contains(priority))
+			.ok_or(format!("sched_policy {name} priority need to be between {min} and {max}"))?,
+		None if min == 0 => 0,
+		None => return Err(format!("sched_policy {name} needs a priority, like {name}:{min}")),
+	};
+
+	if priority > 0 && !privileged() {
+		let mut rtprio = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+		unsafe { libc::getrlimit(libc::RLIMIT_RTPRIO, &mut rtprio) };
+		if (priority as libc::rlim_t) > rtprio.rlim_cur {
+			return Err(format!("sched_policy {name}:{priority} needs root or a higher rtprio limit"));
+		}
+	}
+	Ok((policy, priority))
+}
+
+// Unset, or an int within range
+fn int_in(yaml: &Yaml, key: &str, min: i32, max: i32) -> Result<Option<i32>, String> {
+	match &yaml[key] {
+		Yaml::BadValue => Ok(None),
+		value => value.as_i64().and_then(|n| i32::try_from(n).ok()).filter(|n| (min..=max).contains(n)).map(Some)
+			.ok_or(format!("{key} need to be an int between {min} and {max}")),
+	}
+}
+
+impl SchedOptions {
+	pub fn from_yaml(yaml: &Yaml) -> Result<SchedOptions, String> {
+		let nice = int_in(yaml, "nice", -20, 19)?;
+		if let Some(nice) = nice {
+			// Lowering the niceness is privileged
+			let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+			if nice < current && !privileged() {
+				return Err(format!("nice {nice} is below the daemon's own ({current}) and needs root"));
+			}
+		}
+
+		let cpu_affinity = match &yaml["cpu_affinity"] {
+			Yaml::BadValue => None,
+			cores => {
+				let cpus = (unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) } as usize).min(libc::CPU_SETSIZE as usize);
+				let cores = cores.as_vec().ok_or("cpu_affinity need to be a list of cores")?.iter()
+					.map(|core| core.as_i64().and_then(|core| usize::try_from(core).ok()).filter(|core| *core < cpus))
+					.collect::<Option<Vec<usize>>>()
+					.ok_or(format!("cpu_affinity cores need to be between 0 and {}", cpus - 1))?;
+				if cores.is_empty() {
+					return Err("cpu_affinity cannot be empty".to_owned());
+				}
+				Some(cores)
+			}
+		};
+
+		let oom_score_adj = int_in(yaml, "oom_score_adj", -1000, 1000)?;
+		if let Some(adj) = oom_score_adj {
+			if adj < current_oom_score_adj() && !privileged() {
+				return Err("Lowering oom_score_adj needs root".to_owned());
+			}
+		}
+
+		Ok(SchedOptions {
+			nice,
+			ioprio: yaml["ioprio"].as_str().map(parse_ioprio).transpose()?,
+			cpu_affinity,
+			sched_policy: yaml["sched_policy"].as_str().map(parse_sched_policy).transpose()?,
+			oom_score_adj,
+		})
+	}
+
+	// Applied in the child, between fork and exec
+	pub fn apply(&self, command: &mut Command) {
+		if *self == SchedOptions::default() {
+			return;
+		}
+		let opts = self.clone();
+		let oom_score_adj_path = CString::new("/proc/self/oom_score_adj").unwrap();
+		let oom_score_adj = opts.oom_score_adj.map(|adj| adj.to_string());
+
+		unsafe {
+			command.pre_exec(move || {
+				let check = |ret: libc::c_int| if ret < 0 { Err(io::Error::last_os_error()) } else { Ok(()) };
+
+				if let Some((policy, priority)) = opts.sched_policy {
+					let param = libc::sched_param { sched_priority: priority };
+					check(libc::sched_setscheduler(0, policy, &param))?;
+				}
+				if let Some(nice) = opts.nice {
+					check(libc::setpriority(libc::PRIO_PROCESS, 0, nice))?;
+				}
+				if let Some((class, level)) = opts.ioprio {
+					let ioprio = ((class as libc::c_int) << IOPRIO_CLASS_SHIFT) | level;
+					check(libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) as libc::c_int)?;
+				}
+				if let Some(cores) = &opts.cpu_affinity {
+					let mut set: libc::cpu_set_t = std::mem::zeroed();
+					for core in cores {
+						libc::CPU_SET(*core, &mut set);
+					}
+					check(libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set))?;
+				}
+				if let Some(adj) = &oom_score_adj {
+					let fd = libc::open(oom_score_adj_path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+					check(fd)?;
+					let written = libc::write(fd, adj.as_ptr() as *const libc::c_void, adj.len());
+					libc::close(fd);
+					check(written as libc::c_int)?;
+				}
+				Ok(())
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use yaml_rust::YamlLoader;
+
+	use super::{IoClass, SchedOptions};
+
+	fn options(text: &str) -> Result<SchedOptions, String> {
+		SchedOptions::from_yaml(&YamlLoader::load_from_str(text).unwrap().remove(0))
+	}
+
+	// Only what an unprivileged daemon is allowed to set
+	#[test]
+	fn unprivileged_options() {
+		let opts = options("nice: 19\nioprio: best-effort:6\ncpu_affinity: [0]\nsched_policy: batch\noom_score_adj: 1000").unwrap();
+		assert_eq!(opts.nice, Some(19));
+		assert_eq!(opts.ioprio, Some((IoClass::BestEffort, 6)));
+		assert_eq!(opts.cpu_affinity, Some(vec![0]));
+		assert_eq!(opts.sched_policy, Some((libc::SCHED_BATCH, 0)));
+		assert_eq!(opts.oom_score_adj, Some(1000));
+
+		// The idle class has no level, the default one is 4
+		assert_eq!(options("ioprio: idle").unwrap().ioprio, Some((IoClass::Idle, 0)));
+		assert_eq!(options("ioprio: be").unwrap().ioprio, Some((IoClass::BestEffort, 4)));
+		assert_eq!(options("sched_policy: other").unwrap().sched_policy, Some((libc::SCHED_OTHER, 0)));
+		assert_eq!(options("{}").unwrap(), SchedOptions::default());
+	}
+
+	#[test]
+	fn invalid_options_are_rejected() {
+		assert!(options("nice: 20").is_err());
+		assert!(options("nice: -21").is_err());
+		assert!(options("ioprio: bulk:1").is_err());
+		assert!(options("ioprio: be:8").is_err());
+		assert!(options("cpu_affinity: 0").is_err());
+		assert!(options("cpu_affinity: []").is_err());
+		assert!(options("cpu_affinity: [-1]").is_err());
+		assert!(options("cpu_affinity: [100000]").is_err());
+		assert!(options("sched_policy: fast").is_err());
+		assert!(options("sched_policy: fifo").is_err());
+		assert!(options("sched_policy: rr:1000").is_err());
+		assert!(options("oom_score_adj: 1001").is_err());
+
+		// Set but not an int is an error too, not an unset option
+		assert_eq!(options("nice: high").unwrap_err(), "nice need to be an int between -20 and 19");
+		assert!(options("nice: 1.5").is_err());
+		assert!(options("nice: 4294967315").is_err());
+		assert_eq!(options("oom_score_adj: lots").unwrap_err(), "oom_score_adj need to be an int between -1000 and 1000");
+		assert!(options("oom_score_adj: -1001").is_err());
+		assert!(options("oom_score_adj: 4294967296").is_err());
+	}
+}
@@ -0,0 +1,213 @@
+use std::{collections::HashMap, net::TcpListener, os::{fd::{AsRawFd, OwnedFd, RawFd}, unix::{net::UnixListener, process::CommandExt}}, process::Command, sync::Mutex};
+
+use lazy_static::lazy_static;
+use serde::{Serialize, Deserialize};
+use yaml_rust::Yaml;
+
+const SHIM_ENV: &str = "TASKMASTERD_SOCKETS";
+const LISTEN_FDS_START: RawFd = 3;
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+pub enum SocketAddress {
+	Tcp(String), // host:port
+	Unix(String),
+}
+
+// Listening sockets opened by the daemon and passed to the program like systemd does, by name
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct SocketOptions {
+	sockets: Vec<(String, SocketAddress)>,
+}
+
+lazy_static! {
+	// Kept open across restarts and reloads, a restarted program never drops the port
+	static ref OPEN: Mutex<HashMap<SocketAddress, OwnedFd>> = Mutex::new(HashMap::new());
+}
+
+impl SocketAddress {
+	fn parse(address: &str) -> Result<SocketAddress, String> {
+		if address.starts_with('/') {
+			return Ok(SocketAddress::Unix(address.to_owned()));
+		}
+		match address.rsplit_once(':') {
+			Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(SocketAddress::Tcp(address.to_owned())),
+			_ => Err(format!("Invalid socket {address}, expected host:port or an absolute path")),
+		}
+	}
+
+	fn bind(&self) -> Result<OwnedFd, String> {
+		match self {
+			SocketAddress::Tcp(address) => TcpListener::bind(address).map(OwnedFd::from),
+			SocketAddress::Unix(path) => {
+				// Left behind by a previous daemon
+				if let Err(err) = std::fs::remove_file(path) {
+					if err.kind() != std::io::ErrorKind::NotFound {
+						return Err(format!("Could not bind {path}: {err}"));
+					}
+				}
+				UnixListener::bind(path).map(OwnedFd::from)
+			}
+		}.map_err(|err| format!("Could not bind {self}: {err}"))
+	}
+}
+
+impl std::fmt::Display for SocketAddress {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			SocketAddress::Tcp(address) | SocketAddress::Unix(address) => write!(f, "{address}"),
+		}
+	}
+}
+
+impl SocketOptions {
+	// A map of names to host:port or socket paths, the order is the one of the fds
+	pub fn from_yaml(yaml: &Yaml) -> Result<SocketOptions, String> {
+		if yaml.is_badvalue() {
+			return Ok(SocketOptions::default());
+		}
+		let sockets = yaml.as_hash().ok_or("sockets need to be a map of names to addresses")?;
+
+		let sockets = sockets.iter().map(|(name, address)| {
+			let name = name.as_str().ok_or("sockets names need to be strings")?;
+			// LISTEN_FDNAMES is separated by colons
+			if name.is_empty() || name.contains(':') {
+				return Err(format!("Invalid socket name {name}"));
+			}
+			let address = address.as_str().ok_or(format!("sockets.{name} need to be host:port or a path"))?;
+			Ok((name.to_owned(), SocketAddress::parse(address)?))
+		}).collect::<Result<_, String>>()?;
+		Ok(SocketOptions { sockets })
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.sockets.is_empty()
+	}
+
+	pub fn addresses(&self) -> impl Iterator<Item = &SocketAddress> {
+		self.sockets.iter().map(|(_, address)| address)
+	}
+
+	// Open the sockets if needed, the shim started instead of the program puts them at fd 3 onward
+	pub fn attach(&self, command: &mut Command) -> Result<(), String> {
+		let mut open = OPEN.lock().unwrap();
+		let mut fds = Vec::new();
+		for (_, address) in &self.sockets {
+			if !open.contains_key(address) {
+				open.insert(address.clone(), address.bind()?);
+			}
+			fds.push(open[address].as_raw_fd());
+		}
+
+		command.env(SHIM_ENV, fds.iter().map(RawFd::to_string).collect::<Vec<_>>().join(","));
+		command.env("LISTEN_FDNAMES", self.sockets.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(":"));
+		unsafe {
+			command.pre_exec(move || {
+				for fd in &fds {
+					let flags = libc::fcntl(*fd, libc::F_GETFD);
+					libc::fcntl(*fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC);
+				}
+				Ok(())
+			});
+		}
+		Ok(())
+	}
+}
+
+// Close the sockets no loaded program uses anymore
+pub fn release_unused<'a>(used: impl Iterator<Item = &'a SocketAddress>) {
+	let used: Vec<&SocketAddress> = used.collect();
+	OPEN.lock().unwrap().retain(|address, _| {
+		let keep = used.contains(&address);
+		if let (false, SocketAddress::Unix(path)) = (keep, address) {
+			let _ = std::fs::remove_file(path);
+		}
+		keep
+	});
+}
+
+// For daemon upgrade, the fds stay open across the exec
+pub fn handed_over() -> Vec<(SocketAddress, RawFd)> {
+	OPEN.lock().unwrap().iter().map(|(address, fd)| (address.clone(), fd.as_raw_fd())).collect()
+}
+
+pub fn inherit(sockets: Vec<(SocketAddress, OwnedFd)>) {
+	OPEN.lock().unwrap().extend(sockets);
+}
+
+// Run between the daemon and the program, LISTEN_PID can only be known once forked
+pub fn is_shim() -> bool {
+	std::env::var_os(SHIM_ENV).is_some()
+}
+
+pub fn exec_program() -> ! {
+	let fds: Vec<RawFd> = std::env::var(SHIM_ENV).unwrap_or_default()
+		.split(',').filter_map(|fd| fd.parse().ok()).collect();
+	std::env::remove_var(SHIM_ENV);
+
+	// Moved out of the way first, a socket may already sit where another one goes
+	let count = fds.len() as RawFd;
+	let moved: Vec<RawFd> = fds.iter().map(|fd| unsafe {
+		let moved = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, LISTEN_FDS_START + count);
+		libc::close(*fd);
+		moved
+	}).collect();
+	for (i, fd) in moved.iter().enumerate() {
+		unsafe { libc::dup2(*fd, LISTEN_FDS_START + i as RawFd) };
+	}
+	std::env::set_var("LISTEN_FDS", count.to_string());
+	std::env::set_var("LISTEN_PID", std::process::id().to_string());
+
+	let mut argv = std::env::args_os().skip(1);
+	let Some(program) = argv.next() else { std::process::exit(127) };
+	let err = Command::new(&program).args(argv).exec();
+	eprintln!("taskmasterd: could not run {}: {err}", program.to_string_lossy());
+	std::process::exit(127);
+}
+
+#[cfg(test)]
+mod tests {
+	use yaml_rust::{Yaml, YamlLoader};
+
+	use super::{SocketAddress, SocketOptions};
+
+	fn sockets(text: &str) -> Result<SocketOptions, String> {
+		SocketOptions::from_yaml(&YamlLoader::load_from_str(text).unwrap().remove(0))
+	}
+
+	#[test]
+	fn tcp_and_unix_addresses() {
+		assert_eq!(SocketAddress::parse("127.0.0.1:8080"), Ok(SocketAddress::Tcp("127.0.0.1:8080".to_owned())));
+		assert_eq!(SocketAddress::parse("localhost:0"), Ok(SocketAddress::Tcp("localhost:0".to_owned())));
+		assert_eq!(SocketAddress::parse("[::1]:443"), Ok(SocketAddress::Tcp("[::1]:443".to_owned())));
+		assert_eq!(SocketAddress::parse("/run/app.sock"), Ok(SocketAddress::Unix("/run/app.sock".to_owned())));
+	}
+
+	#[test]
+	fn invalid_addresses_are_rejected() {
+		assert!(SocketAddress::parse("8080").is_err());
+		assert!(SocketAddress::parse(":8080").is_err());
+		assert!(SocketAddress::parse("localhost:http").is_err());
+		assert!(SocketAddress::parse("localhost:65536").is_err());
+		assert!(SocketAddress::parse("run/app.sock").is_err());
+	}
+
+	#[test]
+	fn sockets_keep_their_order() {
+		assert!(SocketOptions::from_yaml(&Yaml::BadValue).unwrap().is_empty());
+
+		let opts = sockets("web: 0.0.0.0:80\nadmin: /tmp/admin.sock").unwrap();
+		assert_eq!(opts.sockets, vec![
+			("web".to_owned(), SocketAddress::Tcp("0.0.0.0:80".to_owned())),
+			("admin".to_owned(), SocketAddress::Unix("/tmp/admin.sock".to_owned())),
+		]);
+	}
+
+	#[test]
+	fn invalid_sockets_are_rejected() {
+		assert!(sockets("[web]").is_err());
+		assert!(sockets("\"a:b\": /tmp/a.sock").is_err());
+		assert!(sockets("\"\": /tmp/a.sock").is_err());
+		assert!(sockets("web: 80").is_err());
+		assert!(sockets("web: [80]").is_err());
+	}
+}
@@ -0,0 +1,233 @@
+use std::{fs, io::{Read, Seek, SeekFrom, Write}, os::fd::{FromRawFd, RawFd}};
+
+use serde::{Serialize, Deserialize};
+
+use crate::sockets::SocketAddress;
+
+const STATE_PATH: &str = "/tmp/taskmasterd.state";
+const UPGRADE_ENV: &str = "TASKMASTERD_UPGRADE_FD";
+
+// What a restarted daemon needs to pick up where the last one stopped
+#[derive(Serialize, Deserialize, PartialEq, Default, Debug)]
+pub struct SavedState {
+	pub files: Vec<SavedFile>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct SavedFile {
+	pub path: String,
+	pub processes: Vec<SavedProcess>,
+	pub scaled: Vec<(String, u64)>, // numprocs set by scale, by program
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct SavedProcess {
+	pub program: String,
+	pub index: usize,
+	pub pid: Option<u32>,
+	pub starttime: u64, // clock ticks since boot, tells a recycled pid apart
+	pub retries: u64,
+	pub pidfd: Option<RawFd>, // only set when handed over by an upgrade, as are the pipes
+	pub stdin: Option<RawFd>,
+	pub stdout: Option<RawFd>, // read by the daemon when the process is attachable
+	pub stderr: Option<RawFd>,
+}
+
+// Everything a re-executed daemon inherits
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Upgrade {
+	pub listener: RawFd,
+	pub client: RawFd, // answered by the new daemon once it is up
+	pub sockets: Vec<(SocketAddress, RawFd)>,
+	pub state: SavedState,
+}
+
+impl Upgrade {
+	// Everything that has to stay open across the exec
+	pub fn fds(&self) -> Vec<RawFd> {
+		let processes = self.state.files.iter().flat_map(|file| &file.processes);
+		[self.listener, self.client].into_iter()
+			.chain(self.sockets.iter().map(|(_, fd)| *fd))
+			.chain(processes.flat_map(|process| [process.pidfd, process.stdin, process.stdout, process.stderr].into_iter().flatten()))
+			.collect()
+	}
+}
+
+pub fn set_cloexec(fd: RawFd, cloexec: bool) {
+	unsafe {
+		let flags = libc::fcntl(fd, libc::F_GETFD);
+		libc::fcntl(fd, libc::F_SETFD, if cloexec { flags | libc::FD_CLOEXEC } else { flags & !libc::FD_CLOEXEC });
+	}
+}
+
+// The upgrade goes through a memfd left open across the exec, its number in the environment
+pub fn hand_over(upgrade: &Upgrade) -> Result<(String, String), String> {
+	let fd = unsafe { libc::memfd_create(c"taskmasterd-upgrade".as_ptr(), 0) };
+	if fd < 0 {
+		return Err(format!("memfd_create: {}", std::io::Error::last_os_error()));
+	}
+	let mut file = unsafe { fs::File::from_raw_fd(fd) };
+	let upgrade = bincode::serialize(upgrade).map_err(|err| err.to_string())?;
+	file.write_all(&upgrade).and_then(|_| file.seek(SeekFrom::Start(0))).map_err(|err| err.to_string())?;
+
+	// Kept open for the exec
+	std::mem::forget(file);
+	Ok((UPGRADE_ENV.to_owned(), fd.to_string()))
+}
+
+// What the daemon we were re-executed from handed over, if it did
+pub fn inherited() -> Option<Upgrade> {
+	let fd = std::env::var(UPGRADE_ENV).ok()?.parse::<RawFd>().ok()?;
+	std::env::remove_var(UPGRADE_ENV);
+
+	let mut file = unsafe { fs::File::from_raw_fd(fd) };
+	let mut upgrade = Vec::new();
+	file.read_to_end(&mut upgrade).ok()?;
+	match bincode::deserialize::<Upgrade>(&upgrade) {
+		Ok(upgrade) => {
+			// Not for the processes we spawn from now on
+			for fd in upgrade.fds() {
+				set_cloexec(fd, true);
+			}
+			Some(upgrade)
+		}
+		Err(err) => {
+			eprintln!("Ignoring the upgrade state: {err}");
+			None
+		}
+	}
+}
+
+
+pub fn load() -> Option<SavedState> {
+	load_from(STATE_PATH)
+}
+
+fn load_from(path: &str) -> Option<SavedState> {
+	let state = fs::read(path).ok()?;
+	match bincode::deserialize(&state) {
+		Ok(state) => Some(state),
+		Err(err) => {
+			eprintln!("Ignoring {path}: {err}");
+			None
+		}
+	}
+}
+
+pub fn save(state: &SavedState) {
+	save_to(state, STATE_PATH);
+}
+
+// Written aside then renamed, a crash never leaves half a file
+fn save_to(state: &SavedState, path: &str) {
+	let tmp = format!("{path}.tmp");
+	let saved = bincode::serialize(state).map_err(|err| err.to_string())
+		.and_then(|state| fs::write(&tmp, state).map_err(|err| err.to_string()))
+		.and_then(|_| fs::rename(&tmp, path).map_err(|err| err.to_string()));
+
+	if let Err(err) = saved {
+		eprintln!("Could not save {path}: {err}");
+	}
+}
+
+// After a clean shutdown there is nothing to pick up
+pub fn clear() {
+	let _ = fs::remove_file(STATE_PATH);
+}
+
+#[cfg(test)]
+mod tests {
+	use std::os::fd::{AsRawFd, OwnedFd, FromRawFd};
+
+	use crate::sockets::SocketAddress;
+
+	use super::{hand_over, inherited, load_from, save_to, SavedFile, SavedProcess, SavedState, Upgrade, UPGRADE_ENV};
+
+	fn process(program: &str, index: usize, pid: Option<u32>) -> SavedProcess {
+		SavedProcess { program: program.to_owned(), index, pid, starttime: 4242, retries: 2, pidfd: None, stdin: None, stdout: None, stderr: None }
+	}
+
+	fn path(name: &str) -> String {
+		std::env::temp_dir().join(format!("taskmasterd-test-{}-{name}.state", std::process::id())).to_string_lossy().into_owned()
+	}
+
+	#[test]
+	fn saved_state_round_trip() {
+		let state = SavedState {
+			files: vec![SavedFile {
+				path: "/etc/taskmaster/web.yaml".to_owned(),
+				processes: vec![process("web", 0, Some(1234)), process("web", 1, None)],
+				scaled: vec![("web".to_owned(), 2)],
+			}],
+		};
+		let path = path("round-trip");
+		save_to(&state, &path);
+		let loaded = load_from(&path);
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(loaded, Some(state));
+	}
+
+	#[test]
+	fn unreadable_state_is_ignored() {
+		assert_eq!(load_from(&path("missing")), None);
+
+		let path = path("garbage");
+		std::fs::write(&path, [0xff; 3]).unwrap();
+		let loaded = load_from(&path);
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(loaded, None);
+	}
+
+	fn pipe() -> (OwnedFd, OwnedFd) {
+		let mut fds = [0; 2];
+		assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+		unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) }
+	}
+
+	fn cloexec(fd: &OwnedFd) -> bool {
+		unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) & libc::FD_CLOEXEC != 0 }
+	}
+
+	#[test]
+	fn upgrade_keeps_every_fd_open() {
+		let mut attachable = process("cat", 0, Some(1234));
+		(attachable.pidfd, attachable.stdin, attachable.stdout, attachable.stderr) = (Some(10), Some(11), Some(12), Some(13));
+		let mut running = process("web", 0, Some(1235));
+		running.pidfd = Some(14);
+		let upgrade = Upgrade {
+			listener: 3,
+			client: 4,
+			sockets: vec![(SocketAddress::Tcp("127.0.0.1:8080".to_owned()), 5)],
+			state: SavedState {
+				files: vec![
+					SavedFile { path: "cat.yaml".to_owned(), processes: vec![attachable, process("cat", 1, None)], scaled: Vec::new() },
+					SavedFile { path: "web.yaml".to_owned(), processes: vec![running], scaled: Vec::new() },
+				],
+			},
+		};
+		assert_eq!(upgrade.fds(), vec![3, 4, 5, 10, 11, 12, 13, 14]);
+	}
+
+	#[test]
+	fn upgrade_handed_over_and_inherited() {
+		let (listener, client) = pipe();
+		let (stdout, pidfd) = pipe();
+		for fd in [&listener, &client, &stdout, &pidfd] {
+			super::set_cloexec(fd.as_raw_fd(), false);
+		}
+		let mut handed = process("cat", 0, Some(1234));
+		(handed.pidfd, handed.stdout) = (Some(pidfd.as_raw_fd()), Some(stdout.as_raw_fd()));
+		let state = SavedState { files: vec![SavedFile { path: "cat.yaml".to_owned(), processes: vec![handed], scaled: Vec::new() }] };
+		let upgrade = Upgrade { listener: listener.as_raw_fd(), client: client.as_raw_fd(), sockets: Vec::new(), state };
+
+		let (key, value) = hand_over(&upgrade).unwrap();
+		assert_eq!(key, UPGRADE_ENV);
+		std::env::set_var(&key, &value);
+		let inherited = inherited().unwrap();
+		assert!(std::env::var(UPGRADE_ENV).is_err());
+		assert_eq!(inherited.fds(), upgrade.fds());
+		assert_eq!(inherited.state, upgrade.state);
+		// Not leaked to the processes spawned from then on
+		assert!([&listener, &client, &stdout, &pidfd].into_iter().all(cloexec));
+	}
+}
@@ -0,0 +1,90 @@
+use std::{collections::HashMap, fmt, time::{Duration, Instant}};
+
+use taskmastersocket::{format_duration, format_size};
+
+use crate::procfs;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Stats {
+	pub sampled_at: Instant,
+	pub cpu_ticks: u64, // used since the process started
+	pub cpu_percent: f64,
+	pub rss_bytes: u64,
+	pub threads: u64,
+	pub fds: usize,
+	pub uptime_sec: u64,
+}
+
+impl fmt::Display for Stats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "cpu {:.1}%, rss {}, {} threads, {} fds, up {}",
+			self.cpu_percent, format_size(self.rss_bytes), self.threads, self.fds, format_duration(self.uptime_sec))
+	}
+}
+
+// Usage of a process, cpu is measured between two samples
+pub struct Sampler {
+	pid: u32,
+	next_at: Instant,
+	last_cpu: Option<(Instant, u64)>,
+	pub current: Option<Stats>,
+}
+
+impl Sampler {
+	pub fn new(pid: u32) -> Sampler {
+		Sampler {
+			pid,
+			next_at: Instant::now(),
+			last_cpu: None,
+			current: None,
+		}
+	}
+
+	pub fn sample(&mut self) {
+		if Instant::now() < self.next_at {
+			return;
+		}
+		self.next_at = Instant::now() + SAMPLE_INTERVAL;
+
+		let (Ok(stat), Ok(status)) = (procfs::stat(self.pid), procfs::status(self.pid)) else {
+			self.current = None;
+			return;
+		};
+		let ticks = procfs::clock_ticks() as f64;
+		let cpu_ticks = stat.utime + stat.stime;
+		let age = (procfs::uptime().unwrap_or(0.0) - stat.starttime as f64 / ticks).max(0.0);
+
+		// The first sample can only give the average since the process started
+		let cpu_percent = match self.last_cpu {
+			Some((at, last)) => (cpu_ticks - last) as f64 / ticks / at.elapsed().as_secs_f64() * 100.0,
+			None if age > 0.0 => cpu_ticks as f64 / ticks / age * 100.0,
+			None => 0.0,
+		};
+		self.last_cpu = Some((Instant::now(), cpu_ticks));
+
+		self.current = Some(Stats {
+			sampled_at: Instant::now(),
+			cpu_ticks,
+			cpu_percent,
+			rss_bytes: status.get("VmRSS").map_or(stat.rss_bytes, |kb| kb * 1024),
+			threads: status.get("Threads").copied().unwrap_or(1),
+			fds: procfs::open_fds(self.pid).unwrap_or(0),
+			uptime_sec: age as u64,
+		});
+	}
+}
+
+// Descendants of pid, one per line and indented by depth
+pub fn tree(pid: u32, children: &HashMap<u32, Vec<u32>>, ident: &str) -> String {
+	let mut tree = String::new();
+
+	for child in children.get(&pid).into_iter().flatten() {
+		let Ok(stat) = procfs::stat(*child) else { continue };
+		let cmdline = procfs::cmdline(*child).ok().filter(|cmd| !cmd.is_empty()).unwrap_or(format!("[{}]", stat.comm));
+
+		tree.push_str(&format!("{ident}{child} ({}) {cmdline}\n", stat.state));
+		tree.push_str(&self::tree(*child, children, &format!("{ident}  ")));
+	}
+	tree
+}
@@ -0,0 +1,117 @@
+// Values of a process for %(name)s and %(name)d, like supervisord
+pub struct Context<'a> {
+	pub program_name: &'a str,
+	pub process_num: u64,
+	pub here: &'a str, // directory of the config file
+}
+
+enum Value {
+	Number(u64),
+	Text(String),
+}
+
+impl Context<'_> {
+	fn value(&self, name: &str) -> Result<Value, String> {
+		match name {
+			"process_num" => Ok(Value::Number(self.process_num)),
+			"program_name" => Ok(Value::Text(self.program_name.to_owned())),
+			"here" => Ok(Value::Text(self.here.to_owned())),
+			_ => match name.strip_prefix("ENV_") {
+				Some(var) => std::env::var(var).map(Value::Text).map_err(|_| format!("%({name}) but {var} is not set")),
+				None => Err(format!("Unknown variable %({name})")),
+			},
+		}
+	}
+}
+
+// %(name) followed by an optional width, 0 padded when it starts with 0, and s or d, %% for a %
+pub fn expand(template: &str, context: &Context) -> Result<String, String> {
+	let mut expanded = String::new();
+	let mut rest = template;
+
+	while let Some(start) = rest.find('%') {
+		expanded.push_str(&rest[..start]);
+		rest = &rest[start + 1..];
+
+		if let Some(after) = rest.strip_prefix('%') {
+			expanded.push('%');
+			rest = after;
+			continue;
+		}
+		let invalid = || format!("Invalid template in {template}, expected %(name)s or %(name)d, or %% for a literal %");
+		let (name, after) = rest.strip_prefix('(').and_then(|rest| rest.split_once(')')).ok_or_else(invalid)?;
+
+		let spec_len = after.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+		let (width, after) = after.split_at(spec_len);
+		let zero = width.starts_with('0');
+		let width = width.parse::<usize>().unwrap_or(0);
+
+		let value = match (after.chars().next(), context.value(name)?) {
+			(Some('d'), Value::Number(n)) if zero => format!("{n:0width$}"),
+			(Some('d'), Value::Number(n)) => format!("{n:>width$}"),
+			(Some('d'), Value::Text(_)) => return Err(format!("%({name}) is not a number, use %({name})s")),
+			(Some('s'), Value::Number(n)) => format!("{n:>width$}"),
+			(Some('s'), Value::Text(text)) => format!("{text:>width$}"),
+			_ => return Err(invalid()),
+		};
+		expanded.push_str(&value);
+		rest = &after[1..];
+	}
+	expanded.push_str(rest);
+	Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{expand, Context};
+
+	const CONTEXT: Context = Context { program_name: "web", process_num: 7, here: "/etc/taskmaster" };
+
+	fn expanded(template: &str) -> Result<String, String> {
+		expand(template, &CONTEXT)
+	}
+
+	#[test]
+	fn variables_are_expanded() {
+		assert_eq!(expanded("%(program_name)s-%(process_num)d").unwrap(), "web-7");
+		assert_eq!(expanded("%(here)s/web.log").unwrap(), "/etc/taskmaster/web.log");
+		assert_eq!(expanded("no template").unwrap(), "no template");
+		assert_eq!(expanded("").unwrap(), "");
+	}
+
+	#[test]
+	fn percent_is_escaped() {
+		assert_eq!(expanded("100%%").unwrap(), "100%");
+		assert_eq!(expanded("%%(process_num)d").unwrap(), "%(process_num)d");
+		assert_eq!(expanded("%%%(process_num)d%%").unwrap(), "%7%");
+	}
+
+	#[test]
+	fn width_and_padding() {
+		assert_eq!(expanded("%(process_num)02d").unwrap(), "07");
+		assert_eq!(expanded("%(process_num)3d").unwrap(), "  7");
+		assert_eq!(expanded("%(process_num)s").unwrap(), "7");
+		assert_eq!(expanded("%(program_name)5s").unwrap(), "  web");
+		// Wider values are not cut
+		assert_eq!(expanded("%(program_name)1s").unwrap(), "web");
+	}
+
+	#[test]
+	fn environment_variables() {
+		let path = std::env::var("PATH").unwrap();
+		assert_eq!(expanded("%(ENV_PATH)s").unwrap(), path);
+		let err = expanded("%(ENV_TASKMASTER_TEST_UNSET)s").unwrap_err();
+		assert!(err.contains("TASKMASTER_TEST_UNSET is not set"), "{err}");
+	}
+
+	#[test]
+	fn invalid_templates_are_rejected() {
+		assert_eq!(expanded("%(nope)s").unwrap_err(), "Unknown variable %(nope)");
+		assert!(expanded("%(program_name)d").unwrap_err().contains("is not a number"));
+		// A literal % has to be written %%
+		for template in ["50% off", "%", "%(process_num)", "%(process_num)x", "%(process_num"] {
+			let err = expanded(template).unwrap_err();
+			assert!(err.contains("%% for a literal %"), "{template}: {err}");
+		}
+	}
+}
@@ -0,0 +1,62 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+use taskmastersocket::format_size;
+
+use crate::{procfs, stats::Stats, TaskOptions};
+
+// Checks the samples of a running process against its max_* options
+pub struct Watchdog {
+	cpu_samples: VecDeque<(Instant, u64)>, // total cpu ticks used at that time
+}
+
+impl Watchdog {
+	pub fn new() -> Watchdog {
+		Watchdog {
+			cpu_samples: VecDeque::new(),
+		}
+	}
+
+	// Reason to restart the process, if it is over one of its limits, each sample is only checked once
+	pub fn check(&mut self, stats: &Stats, opts: &TaskOptions) -> Option<String> {
+		if self.cpu_samples.back().is_some_and(|(at, _)| *at >= stats.sampled_at) {
+			return None;
+		}
+		let now = stats.sampled_at;
+		let window = Duration::from_secs(opts.cpu_window_sec);
+
+		self.cpu_samples.push_back((now, stats.cpu_ticks));
+		// Keep a single sample older than the window to measure all of it
+		while self.cpu_samples.len() > 2 && now - self.cpu_samples[1].0 >= window {
+			self.cpu_samples.pop_front();
+		}
+
+		if let Some(max_rss) = opts.max_rss {
+			if stats.rss_bytes > max_rss {
+				return Some(format!("rss {} exceeds max_rss {}", format_size(stats.rss_bytes), format_size(max_rss)));
+			}
+		}
+
+		if let Some(max_open_fds) = opts.max_open_fds {
+			if stats.fds as u64 > max_open_fds {
+				return Some(format!("{} open fds exceed max_open_fds {max_open_fds}", stats.fds));
+			}
+		}
+
+		if let Some(max_cpu_percent) = opts.max_cpu_percent {
+			let (oldest_at, oldest_ticks) = *self.cpu_samples.front()?;
+			let elapsed = (now - oldest_at).as_secs_f64();
+			if now - oldest_at >= window && elapsed > 0.0 {
+				let used = (stats.cpu_ticks - oldest_ticks) as f64 / procfs::clock_ticks() as f64;
+				let percent = used / elapsed * 100.0;
+				if percent > max_cpu_percent {
+					return Some(format!(
+						"cpu {percent:.0}% over {}s exceeds max_cpu_percent {max_cpu_percent}%",
+						opts.cpu_window_sec
+					));
+				}
+			}
+		}
+
+		None
+	}
+}
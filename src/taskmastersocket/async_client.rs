@@ -0,0 +1,204 @@
+use std::{future::Future, path::Path, pin::Pin, task::{Context, Poll}, time::Duration};
+
+use futures_core::Stream;
+use tokio::net::UnixStream;
+
+use crate::{
+	TaskmasterDaemonRequest, TaskmasterDaemonResult, TaskmasterEvent, LogStream, ProcessSnapshot,
+	read_frame_async, write_frame_async, PROTOCOL_VERSION, DEFAULT_TIMEOUT,
+	client::{ClientError, ClientResult, breaks_connection, into_version, into_success, into_text, into_snapshot, into_event}
+};
+
+// Same as TaskmasterClient but never blocks the runtime
+pub struct AsyncTaskmasterClient {
+	stream: UnixStream,
+	version: u32,
+	timeout: Option<Duration>,
+	desynchronized: bool,
+}
+
+impl AsyncTaskmasterClient {
+	pub async fn connect<P: AsRef<Path>>(path: P) -> ClientResult<AsyncTaskmasterClient> {
+		AsyncTaskmasterClient::connect_timeout(path, Some(DEFAULT_TIMEOUT)).await
+	}
+
+	pub async fn connect_timeout<P: AsRef<Path>>(path: P, timeout: Option<Duration>) -> ClientResult<AsyncTaskmasterClient> {
+		let stream = UnixStream::connect(path).await.map_err(ClientError::Connect)?;
+		let mut client = AsyncTaskmasterClient { stream, version: 0, timeout, desynchronized: false };
+
+		client.version = into_version(client.request(&TaskmasterDaemonRequest::Hello(PROTOCOL_VERSION)).await?)?;
+
+		Ok(client)
+	}
+
+	// None waits forever for the daemon
+	pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+		self.timeout = timeout;
+	}
+
+	pub fn protocol_version(&self) -> u32 {
+		self.version
+	}
+
+	// Send any request and return the raw answer, daemon errors included
+	pub async fn request(&mut self, request: &TaskmasterDaemonRequest) -> ClientResult<TaskmasterDaemonResult> {
+		if self.desynchronized {
+			return Err(ClientError::Desynchronized);
+		}
+
+		let exchange = async {
+			write_frame_async(&mut self.stream, request).await?;
+			Ok(read_frame_async(&mut self.stream).await?)
+		};
+
+		let result = match self.timeout {
+			Some(timeout) => tokio::time::timeout(timeout, exchange).await.map_err(|_| ClientError::Timeout).and_then(|result| result),
+			None => exchange.await,
+		};
+		if let Err(err) = &result {
+			self.desynchronized = breaks_connection(err);
+		}
+		result
+	}
+
+	pub async fn status(&mut self) -> ClientResult<String> {
+		into_text(self.request(&TaskmasterDaemonRequest::Status).await?)
+	}
+
+	// Status with the descendants of each process
+	pub async fn status_tree(&mut self) -> ClientResult<String> {
+		into_text(self.request(&TaskmasterDaemonRequest::StatusTree).await?)
+	}
+
+	pub async fn snapshot(&mut self) -> ClientResult<Vec<ProcessSnapshot>> {
+		into_snapshot(self.request(&TaskmasterDaemonRequest::Snapshot).await?)
+	}
+
+	pub async fn reload(&mut self) -> ClientResult<()> {
+		into_success(self.request(&TaskmasterDaemonRequest::Reload).await?)
+	}
+
+	pub async fn restart_all(&mut self) -> ClientResult<()> {
+		into_success(self.request(&TaskmasterDaemonRequest::Restart).await?)
+	}
+
+	// The daemon exits without answering
+	pub async fn shutdown(mut self) -> ClientResult<()> {
+		Ok(write_frame_async(&mut self.stream, &TaskmasterDaemonRequest::Stop).await?)
+	}
+
+	pub async fn start(&mut self, id: usize) -> ClientResult<()> {
+		into_success(self.request(&TaskmasterDaemonRequest::StartTask(id)).await?)
+	}
+
+	pub async fn stop(&mut self, id: usize) -> ClientResult<()> {
+		into_success(self.request(&TaskmasterDaemonRequest::StopTask(id)).await?)
+	}
+
+	pub async fn restart(&mut self, id: usize) -> ClientResult<()> {
+		into_success(self.request(&TaskmasterDaemonRequest::RestartTask(id)).await?)
+	}
+
+	// Only one process of the task
+	pub async fn start_process(&mut self, id: usize, process: usize) -> ClientResult<()> {
+		into_success(self.request(&TaskmasterDaemonRequest::StartProcess{id, process}).await?)
+	}
+
+	pub async fn stop_process(&mut self, id: usize, process: usize) -> ClientResult<()> {
+		into_success(self.request(&TaskmasterDaemonRequest::StopProcess{id, process}).await?)
+	}
+
+	pub async fn restart_process(&mut self, id: usize, process: usize) -> ClientResult<()> {
+		into_success(self.request(&TaskmasterDaemonRequest::RestartProcess{id, process}).await?)
+	}
+
+	// A batch of processes at a time, each batch past starttime, and its readiness probe with wait_ready
+	pub async fn restart_rolling(&mut self, id: usize, batch: usize, wait_ready: bool) -> ClientResult<String> {
+		into_text(self.request(&TaskmasterDaemonRequest::RestartTaskRolling{id, batch, wait_ready}).await?)
+	}
+
+	pub async fn info(&mut self, id: usize) -> ClientResult<String> {
+		into_text(self.request(&TaskmasterDaemonRequest::InfoTask(id)).await?)
+	}
+
+	pub async fn info_tree(&mut self, id: usize) -> ClientResult<String> {
+		into_text(self.request(&TaskmasterDaemonRequest::InfoTaskTree(id)).await?)
+	}
+
+	// None goes back to the numprocs of the config
+	pub async fn scale(&mut self, program: &str, numprocs: Option<usize>) -> ClientResult<String> {
+		into_text(self.request(&TaskmasterDaemonRequest::ScaleTask{program: program.to_owned(), numprocs}).await?)
+	}
+
+	pub async fn load<P: AsRef<Path>>(&mut self, path: P) -> ClientResult<()> {
+		let path = path.as_ref().to_string_lossy().into_owned();
+		into_success(self.request(&TaskmasterDaemonRequest::LoadFile(path)).await?)
+	}
+
+	pub async fn unload<P: AsRef<Path>>(&mut self, path: P) -> ClientResult<()> {
+		let path = path.as_ref().to_string_lossy().into_owned();
+		into_success(self.request(&TaskmasterDaemonRequest::UnloadFile(path)).await?)
+	}
+
+	// Last lines of the stdout or stderr file of a task
+	pub async fn logs(&mut self, id: usize, stream: LogStream, lines: usize) -> ClientResult<String> {
+		into_text(self.request(&TaskmasterDaemonRequest::LogsTask{id, stream, lines}).await?)
+	}
+
+	// Of one process, when the path of the log depends on %(process_num)d
+	pub async fn logs_process(&mut self, id: usize, process: usize, stream: LogStream, lines: usize) -> ClientResult<String> {
+		into_text(self.request(&TaskmasterDaemonRequest::LogsProcess{id, process, stream, lines}).await?)
+	}
+
+	// Write to the stdin of a process, or of every process of the task
+	pub async fn send(&mut self, id: usize, process: Option<usize>, data: &[u8]) -> ClientResult<()> {
+		into_success(self.request(&TaskmasterDaemonRequest::SendTask{id, process, data: data.to_vec()}).await?)
+	}
+
+	// Which pids received the signal, as text
+	pub async fn signal(&mut self, id: usize, process: Option<usize>, signal: &str) -> ClientResult<String> {
+		into_text(self.request(&TaskmasterDaemonRequest::SignalTask{id, process, signal: signal.to_owned()}).await?)
+	}
+
+	// Answered by the re-executed daemon, which then closes the connection
+	pub async fn upgrade(&mut self) -> ClientResult<()> {
+		into_success(self.request(&TaskmasterDaemonRequest::Upgrade).await?)
+	}
+
+	// Turn the connection into a stream of events, optionally only those of one program
+	pub async fn subscribe(mut self, program: Option<&str>) -> ClientResult<EventStream> {
+		into_success(self.request(&TaskmasterDaemonRequest::Subscribe(program.map(|s| s.to_owned()))).await?)?;
+		Ok(EventStream { next: Some(Box::pin(next_event(self.stream))) })
+	}
+}
+
+type NextEvent = Pin<Box<dyn Future<Output = (UnixStream, Option<ClientResult<TaskmasterEvent>>, bool)> + Send>>;
+
+// The stream is moved in and out of the pending read so EventStream needs no self reference
+async fn next_event(mut stream: UnixStream) -> (UnixStream, Option<ClientResult<TaskmasterEvent>>, bool) {
+	let (item, open) = into_event(read_frame_async(&mut stream).await);
+	(stream, item, open)
+}
+
+pub struct EventStream {
+	next: Option<NextEvent>,
+}
+
+impl Stream for EventStream {
+	type Item = ClientResult<TaskmasterEvent>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let this = self.get_mut();
+		let Some(next) = this.next.as_mut() else {
+			return Poll::Ready(None);
+		};
+
+		match next.as_mut().poll(cx) {
+			Poll::Pending => Poll::Pending,
+			Poll::Ready((stream, item, open)) => {
+				this.next = if open { Some(Box::pin(next_event(stream))) } else { None };
+				Poll::Ready(item)
+			}
+		}
+	}
+}
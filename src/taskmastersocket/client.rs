@@ -0,0 +1,346 @@
+use std::{fmt, io, net::Shutdown, os::unix::net::UnixStream, path::Path, time::Duration};
+
+use crate::{
+	TaskmasterDaemonRequest, TaskmasterDaemonResult, TaskmasterEvent, LogStream, ProcessSnapshot,
+	FrameError, read_frame, write_frame, PROTOCOL_VERSION
+};
+
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/taskmasterd.sock";
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum ClientError {
+	Connect(io::Error),                       // the socket could not be reached
+	Timeout,                                  // the daemon did not answer in time
+	Desynchronized,                           // an earlier request broke off, its answer could be read as ours
+	Frame(FrameError),                        // the connection broke or sent garbage
+	Incompatible(String),                     // the daemon refused our hello
+	Daemon(String),                           // the daemon answered with an error
+	Unexpected(Box<TaskmasterDaemonResult>), // the daemon answered something we did not ask for
+}
+
+impl fmt::Display for ClientError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ClientError::Connect(err) => write!(f, "Could not connect to daemon: {err}"),
+			ClientError::Timeout => write!(f, "Daemon did not answer in time"),
+			ClientError::Desynchronized => write!(f, "Connection to daemon is unusable after an earlier request broke off"),
+			ClientError::Frame(err) => write!(f, "Connection to daemon failed: {err}"),
+			ClientError::Incompatible(err) => write!(f, "Daemon refused the connection: {err}"),
+			ClientError::Daemon(err) => write!(f, "{err}"),
+			ClientError::Unexpected(result) => write!(f, "Unexpected answer from daemon: {result:?}"),
+		}
+	}
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<FrameError> for ClientError {
+	fn from(err: FrameError) -> ClientError {
+		match err {
+			FrameError::Io(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => ClientError::Timeout,
+			err => ClientError::Frame(err),
+		}
+	}
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+// A request that timed out or failed halfway leaves a frame unread or half written
+pub(crate) fn breaks_connection(err: &ClientError) -> bool {
+	matches!(err, ClientError::Timeout | ClientError::Frame(FrameError::Io(_)))
+}
+
+// Interpretation of the daemon answers, shared with the async client
+
+pub(crate) fn into_version(result: TaskmasterDaemonResult) -> ClientResult<u32> {
+	match result {
+		TaskmasterDaemonResult::Hello(version) => Ok(version),
+		TaskmasterDaemonResult::Err(err) => Err(ClientError::Incompatible(err)),
+		result => Err(ClientError::Unexpected(Box::new(result))),
+	}
+}
+
+pub(crate) fn into_success(result: TaskmasterDaemonResult) -> ClientResult<()> {
+	match result {
+		TaskmasterDaemonResult::Success => Ok(()),
+		TaskmasterDaemonResult::Err(err) => Err(ClientError::Daemon(err)),
+		result => Err(ClientError::Unexpected(Box::new(result))),
+	}
+}
+
+pub(crate) fn into_text(result: TaskmasterDaemonResult) -> ClientResult<String> {
+	match result {
+		TaskmasterDaemonResult::Ok(text) | TaskmasterDaemonResult::Raw(text) => Ok(text),
+		TaskmasterDaemonResult::Err(err) => Err(ClientError::Daemon(err)),
+		result => Err(ClientError::Unexpected(Box::new(result))),
+	}
+}
+
+pub(crate) fn into_snapshot(result: TaskmasterDaemonResult) -> ClientResult<Vec<ProcessSnapshot>> {
+	match result {
+		TaskmasterDaemonResult::Snapshot(processes) => Ok(processes),
+		TaskmasterDaemonResult::Err(err) => Err(ClientError::Daemon(err)),
+		result => Err(ClientError::Unexpected(Box::new(result))),
+	}
+}
+
+// Next item of a subscription and whether the connection is still usable
+pub(crate) fn into_event(read: Result<TaskmasterDaemonResult, FrameError>) -> (Option<ClientResult<TaskmasterEvent>>, bool) {
+	match read {
+		Ok(TaskmasterDaemonResult::Event(event)) => (Some(Ok(event)), true),
+		Ok(TaskmasterDaemonResult::Err(err)) => (Some(Err(ClientError::Daemon(err))), true),
+		Ok(result) => (Some(Err(ClientError::Unexpected(Box::new(result)))), true),
+		// The daemon closed the subscription
+		Err(FrameError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => (None, false),
+		Err(err @ FrameError::Decode(_)) => (Some(Err(err.into())), true),
+		Err(err) => (Some(Err(err.into())), false),
+	}
+}
+
+// Blocking connection to taskmasterd, one request is answered at a time
+pub struct TaskmasterClient {
+	stream: UnixStream,
+	version: u32,
+	desynchronized: bool,
+}
+
+impl TaskmasterClient {
+	pub fn connect<P: AsRef<Path>>(path: P) -> ClientResult<TaskmasterClient> {
+		TaskmasterClient::connect_timeout(path, Some(DEFAULT_TIMEOUT))
+	}
+
+	pub fn connect_timeout<P: AsRef<Path>>(path: P, timeout: Option<Duration>) -> ClientResult<TaskmasterClient> {
+		let stream = UnixStream::connect(path).map_err(ClientError::Connect)?;
+		let mut client = TaskmasterClient { stream, version: 0, desynchronized: false };
+
+		client.set_timeout(timeout)?;
+		client.version = into_version(client.request(&TaskmasterDaemonRequest::Hello(PROTOCOL_VERSION))?)?;
+
+		Ok(client)
+	}
+
+	// None waits forever for the daemon
+	pub fn set_timeout(&self, timeout: Option<Duration>) -> ClientResult<()> {
+		self.stream.set_read_timeout(timeout).map_err(ClientError::Connect)?;
+		self.stream.set_write_timeout(timeout).map_err(ClientError::Connect)?;
+		Ok(())
+	}
+
+	pub fn protocol_version(&self) -> u32 {
+		self.version
+	}
+
+	// Send any request and return the raw answer, daemon errors included
+	pub fn request(&mut self, request: &TaskmasterDaemonRequest) -> ClientResult<TaskmasterDaemonResult> {
+		if self.desynchronized {
+			return Err(ClientError::Desynchronized);
+		}
+
+		let result = write_frame(&mut self.stream, request).and_then(|_| read_frame(&mut self.stream)).map_err(ClientError::from);
+		if let Err(err) = &result {
+			self.desynchronized = breaks_connection(err);
+		}
+		result
+	}
+
+	fn expect_success(&mut self, request: &TaskmasterDaemonRequest) -> ClientResult<()> {
+		into_success(self.request(request)?)
+	}
+
+	fn expect_text(&mut self, request: &TaskmasterDaemonRequest) -> ClientResult<String> {
+		into_text(self.request(request)?)
+	}
+
+	pub fn status(&mut self) -> ClientResult<String> {
+		self.expect_text(&TaskmasterDaemonRequest::Status)
+	}
+
+	// Status with the descendants of each process
+	pub fn status_tree(&mut self) -> ClientResult<String> {
+		self.expect_text(&TaskmasterDaemonRequest::StatusTree)
+	}
+
+	pub fn snapshot(&mut self) -> ClientResult<Vec<ProcessSnapshot>> {
+		into_snapshot(self.request(&TaskmasterDaemonRequest::Snapshot)?)
+	}
+
+	pub fn reload(&mut self) -> ClientResult<()> {
+		self.expect_success(&TaskmasterDaemonRequest::Reload)
+	}
+
+	pub fn restart_all(&mut self) -> ClientResult<()> {
+		self.expect_success(&TaskmasterDaemonRequest::Restart)
+	}
+
+	// The daemon exits without answering
+	pub fn shutdown(mut self) -> ClientResult<()> {
+		Ok(write_frame(&mut self.stream, &TaskmasterDaemonRequest::Stop)?)
+	}
+
+	pub fn start(&mut self, id: usize) -> ClientResult<()> {
+		self.expect_success(&TaskmasterDaemonRequest::StartTask(id))
+	}
+
+	pub fn stop(&mut self, id: usize) -> ClientResult<()> {
+		self.expect_success(&TaskmasterDaemonRequest::StopTask(id))
+	}
+
+	pub fn restart(&mut self, id: usize) -> ClientResult<()> {
+		self.expect_success(&TaskmasterDaemonRequest::RestartTask(id))
+	}
+
+	// Only one process of the task
+	pub fn start_process(&mut self, id: usize, process: usize) -> ClientResult<()> {
+		self.expect_success(&TaskmasterDaemonRequest::StartProcess{id, process})
+	}
+
+	pub fn stop_process(&mut self, id: usize, process: usize) -> ClientResult<()> {
+		self.expect_success(&TaskmasterDaemonRequest::StopProcess{id, process})
+	}
+
+	pub fn restart_process(&mut self, id: usize, process: usize) -> ClientResult<()> {
+		self.expect_success(&TaskmasterDaemonRequest::RestartProcess{id, process})
+	}
+
+	// A batch of processes at a time, each batch past starttime, and its readiness probe with wait_ready
+	pub fn restart_rolling(&mut self, id: usize, batch: usize, wait_ready: bool) -> ClientResult<String> {
+		self.expect_text(&TaskmasterDaemonRequest::RestartTaskRolling{id, batch, wait_ready})
+	}
+
+	pub fn info(&mut self, id: usize) -> ClientResult<String> {
+		self.expect_text(&TaskmasterDaemonRequest::InfoTask(id))
+	}
+
+	pub fn info_tree(&mut self, id: usize) -> ClientResult<String> {
+		self.expect_text(&TaskmasterDaemonRequest::InfoTaskTree(id))
+	}
+
+	// None goes back to the numprocs of the config
+	pub fn scale(&mut self, program: &str, numprocs: Option<usize>) -> ClientResult<String> {
+		self.expect_text(&TaskmasterDaemonRequest::ScaleTask{program: program.to_owned(), numprocs})
+	}
+
+	pub fn load<P: AsRef<Path>>(&mut self, path: P) -> ClientResult<()> {
+		let path = path.as_ref().to_string_lossy().into_owned();
+		self.expect_success(&TaskmasterDaemonRequest::LoadFile(path))
+	}
+
+	pub fn unload<P: AsRef<Path>>(&mut self, path: P) -> ClientResult<()> {
+		let path = path.as_ref().to_string_lossy().into_owned();
+		self.expect_success(&TaskmasterDaemonRequest::UnloadFile(path))
+	}
+
+	// Last lines of the stdout or stderr file of a task
+	pub fn logs(&mut self, id: usize, stream: LogStream, lines: usize) -> ClientResult<String> {
+		self.expect_text(&TaskmasterDaemonRequest::LogsTask{id, stream, lines})
+	}
+
+	// Of one process, when the path of the log depends on %(process_num)d
+	pub fn logs_process(&mut self, id: usize, process: usize, stream: LogStream, lines: usize) -> ClientResult<String> {
+		self.expect_text(&TaskmasterDaemonRequest::LogsProcess{id, process, stream, lines})
+	}
+
+	// Write to the stdin of a process, or of every process of the task
+	pub fn send(&mut self, id: usize, process: Option<usize>, data: &[u8]) -> ClientResult<()> {
+		self.expect_success(&TaskmasterDaemonRequest::SendTask{id, process, data: data.to_vec()})
+	}
+
+	// Which pids received the signal, as text
+	pub fn signal(&mut self, id: usize, process: Option<usize>, signal: &str) -> ClientResult<String> {
+		self.expect_text(&TaskmasterDaemonRequest::SignalTask{id, process, signal: signal.to_owned()})
+	}
+
+	// Answered by the re-executed daemon, which then closes the connection
+	pub fn upgrade(&mut self) -> ClientResult<()> {
+		self.expect_success(&TaskmasterDaemonRequest::Upgrade)
+	}
+
+	// Turn the connection into the output of a process, input goes through Attachment::input
+	pub fn attach(mut self, id: usize, process: usize) -> ClientResult<Attachment> {
+		let message = self.expect_text(&TaskmasterDaemonRequest::Attach{id, process})?;
+		self.set_timeout(None)?;
+		Ok(Attachment { stream: self.stream, message, closed: false })
+	}
+
+	// Turn the connection into a stream of events, optionally only those of one program
+	pub fn subscribe(mut self, program: Option<&str>) -> ClientResult<Subscription> {
+		self.expect_success(&TaskmasterDaemonRequest::Subscribe(program.map(|s| s.to_owned())))?;
+		// Events can be far apart, only the subscription itself is subject to the timeout
+		self.set_timeout(None)?;
+		Ok(Subscription { stream: self.stream, closed: false })
+	}
+}
+
+pub struct Subscription {
+	stream: UnixStream,
+	closed: bool,
+}
+
+impl Iterator for Subscription {
+	type Item = ClientResult<TaskmasterEvent>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.closed {
+			return None;
+		}
+
+		let (item, open) = into_event(read_frame(&mut self.stream));
+		self.closed = !open;
+		item
+	}
+}
+
+pub struct Attachment {
+	stream: UnixStream,
+	pub message: String, // what the daemon said when attaching
+	closed: bool,
+}
+
+impl Attachment {
+	// Writing half, usable from another thread while this one reads the output
+	pub fn input(&self) -> ClientResult<AttachInput> {
+		Ok(AttachInput { stream: self.stream.try_clone().map_err(ClientError::Connect)? })
+	}
+}
+
+impl Iterator for Attachment {
+	type Item = ClientResult<(LogStream, Vec<u8>)>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.closed {
+			return None;
+		}
+
+		// Same rules as a subscription
+		Some(match read_frame(&mut self.stream) {
+			Ok(TaskmasterDaemonResult::Output{stream, data}) => Ok((stream, data)),
+			Ok(TaskmasterDaemonResult::Err(err)) => Err(ClientError::Daemon(err)),
+			Ok(result) => Err(ClientError::Unexpected(Box::new(result))),
+			Err(FrameError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+				self.closed = true;
+				return None;
+			}
+			Err(err @ FrameError::Decode(_)) => Err(err.into()),
+			Err(err) => {
+				self.closed = true;
+				Err(err.into())
+			}
+		})
+	}
+}
+
+pub struct AttachInput {
+	stream: UnixStream,
+}
+
+impl AttachInput {
+	pub fn send(&mut self, data: &[u8]) -> ClientResult<()> {
+		Ok(write_frame(&mut self.stream, &TaskmasterDaemonRequest::Input(data.to_vec()))?)
+	}
+
+	// The daemon stops the output once it sees the end of the input
+	pub fn detach(self) -> ClientResult<()> {
+		self.stream.shutdown(Shutdown::Write).map_err(ClientError::Connect)
+	}
+}
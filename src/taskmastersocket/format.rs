@@ -0,0 +1,19 @@
+// Human readable values, shared by the daemon status and the dashboard of the client
+
+pub fn format_size(bytes: u64) -> String {
+	match bytes {
+		b if b >= 1 << 30 => format!("{:.1}G", b as f64 / (1u64 << 30) as f64),
+		b if b >= 1 << 20 => format!("{:.1}M", b as f64 / (1u64 << 20) as f64),
+		b if b >= 1 << 10 => format!("{:.1}K", b as f64 / (1u64 << 10) as f64),
+		b => format!("{b}B"),
+	}
+}
+
+pub fn format_duration(secs: u64) -> String {
+	match secs {
+		s if s >= 86400 => format!("{}d{:02}h", s / 86400, s % 86400 / 3600),
+		s if s >= 3600 => format!("{}h{:02}m", s / 3600, s % 3600 / 60),
+		s if s >= 60 => format!("{}m{:02}s", s / 60, s % 60),
+		s => format!("{s}s"),
+	}
+}
@@ -0,0 +1,104 @@
+use std::{fmt, io::{self, Read, Write}};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+// Frames announcing a bigger payload are refused instead of being allocated
+pub const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+	Io(io::Error),          // the stream failed or was closed, it cannot be used anymore
+	TooLarge(u32),          // the peer announced an oversized frame, the stream is out of sync
+	Decode(bincode::Error), // the payload was skipped, the stream can still be used
+}
+
+impl fmt::Display for FrameError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			FrameError::Io(err) => write!(f, "{err}"),
+			FrameError::TooLarge(len) => write!(f, "frame of {len} bytes exceeds the {MAX_FRAME_LEN} bytes limit"),
+			FrameError::Decode(err) => write!(f, "could not decode frame: {err}"),
+		}
+	}
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+	fn from(err: io::Error) -> FrameError {
+		FrameError::Io(err)
+	}
+}
+
+// A frame is a big endian u32 length followed by the bincode payload
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, FrameError> {
+	let payload = bincode::serialize(value)
+		.map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+	let len = u32::try_from(payload.len()).unwrap_or(u32::MAX);
+	if len > MAX_FRAME_LEN {
+		return Err(FrameError::TooLarge(len));
+	}
+
+	let mut frame = Vec::with_capacity(4 + payload.len());
+	frame.extend_from_slice(&len.to_be_bytes());
+	frame.extend_from_slice(&payload);
+	Ok(frame)
+}
+
+fn payload_len(header: [u8; 4]) -> Result<usize, FrameError> {
+	let len = u32::from_be_bytes(header);
+	if len > MAX_FRAME_LEN {
+		return Err(FrameError::TooLarge(len));
+	}
+	Ok(len as usize)
+}
+
+fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T, FrameError> {
+	bincode::deserialize(payload).map_err(FrameError::Decode)
+}
+
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, value: &T) -> Result<(), FrameError> {
+	writer.write_all(&encode(value)?)?;
+	writer.flush()?;
+	Ok(())
+}
+
+pub fn read_frame<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<T, FrameError> {
+	let mut header = [0u8; 4];
+	reader.read_exact(&mut header)?;
+
+	let mut payload = vec![0u8; payload_len(header)?];
+	reader.read_exact(&mut payload)?;
+
+	decode(&payload)
+}
+
+#[cfg(feature = "async")]
+pub async fn write_frame_async<W, T>(writer: &mut W, value: &T) -> Result<(), FrameError>
+where
+	W: tokio::io::AsyncWrite + Unpin,
+	T: Serialize,
+{
+	use tokio::io::AsyncWriteExt;
+
+	writer.write_all(&encode(value)?).await?;
+	writer.flush().await?;
+	Ok(())
+}
+
+#[cfg(feature = "async")]
+pub async fn read_frame_async<R, T>(reader: &mut R) -> Result<T, FrameError>
+where
+	R: tokio::io::AsyncRead + Unpin,
+	T: DeserializeOwned,
+{
+	use tokio::io::AsyncReadExt;
+
+	let mut header = [0u8; 4];
+	reader.read_exact(&mut header).await?;
+
+	let mut payload = vec![0u8; payload_len(header)?];
+	reader.read_exact(&mut payload).await?;
+
+	decode(&payload)
+}
@@ -1,7 +1,41 @@
 use serde::{Serialize, Deserialize};
 
+mod frame;
+pub use frame::{read_frame, write_frame, FrameError, MAX_FRAME_LEN};
+#[cfg(feature = "async")]
+pub use frame::{read_frame_async, write_frame_async};
+
+mod signal;
+pub use signal::{SIGNALS, parse_signal, signal_name, signal_names};
+
+mod format;
+pub use format::{format_size, format_duration};
+
+mod client;
+pub use client::{TaskmasterClient, Subscription, Attachment, AttachInput, ClientError, ClientResult, DEFAULT_SOCKET_PATH, DEFAULT_TIMEOUT};
+
+#[cfg(feature = "async")]
+mod async_client;
+#[cfg(feature = "async")]
+pub use async_client::{AsyncTaskmasterClient, EventStream};
+
+// Bump when an existing request or result changes shape,
+// appending new variants at the end of the enums does not require it
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+// Version both sides will speak, None if the peer is too old for us
+pub fn negotiate_version(peer: u32) -> Option<u32> {
+	if peer < MIN_PROTOCOL_VERSION {
+		return None;
+	}
+	Some(peer.min(PROTOCOL_VERSION))
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum TaskmasterDaemonRequest {
+	Hello(u32), // protocol version of the client, must stay the first variant
+
 	Status,  // get the status of all process
 	Reload,  // reload all the configs and restart the processes
 	Restart, // restart all the processes
@@ -14,12 +48,100 @@ pub enum TaskmasterDaemonRequest {
 
 	LoadFile(String),
 	UnloadFile(String),
+
+	LogsTask{id: usize, stream: LogStream, lines: usize}, // tail of a task log file
+	Subscribe(Option<String>), // receive events, only those of a program if given
+
+	StatusTree,         // status with the descendants of each process
+	InfoTaskTree(usize),
+	Snapshot,           // state of every process, for clients drawing their own view
+
+	// Write to the stdin pipe of one process, or of all those of the task
+	SendTask{id: usize, process: Option<usize>, data: Vec<u8>},
+	Attach{id: usize, process: usize}, // stream the output of a process, see Input
+	Input(Vec<u8>),                    // only once attached, for the stdin of the process
+
+	// Signal by name, to one process or all of those of the task
+	SignalTask{id: usize, process: Option<usize>, signal: String},
+
+	// Re-exec the daemon binary, supervised processes keep running
+	Upgrade,
+
+	// Restart batch processes at a time, answered once done or aborted
+	RestartTaskRolling{id: usize, batch: usize, wait_ready: bool},
+
+	// Override numprocs by program name until set back to None, reloads included
+	ScaleTask{program: String, numprocs: Option<usize>},
+
+	// One process of a task, as StartTask, StopTask and RestartTask do for all of them
+	StartProcess{id: usize, process: usize},
+	StopProcess{id: usize, process: usize},
+	RestartProcess{id: usize, process: usize},
+
+	// Tail of the log file of one process, LogsTask reads the first one
+	LogsProcess{id: usize, process: usize, stream: LogStream, lines: usize},
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+	Stdout,
+	Stderr,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum TaskmasterEvent {
+	Starting{program: String, process: usize},
+	Running{program: String, process: usize, pid: u32},
+	Exited{program: String, process: usize, code: i32},
+	Backoff{program: String, process: usize, retries: u64}, // about to be respawned
+	Fatal{program: String, process: usize, err: String},    // gave up on the process
+	Stopped{program: String, process: usize},
+
+	ConfigReloaded,
+	FileLoaded(String),
+	FileUnloaded(String),
+
+	Restarting{program: String, process: usize, reason: String}, // restarted by the daemon itself
+}
+
+impl TaskmasterEvent {
+	pub fn program(&self) -> Option<&str> {
+		match self {
+			TaskmasterEvent::Starting{program, ..}
+			| TaskmasterEvent::Running{program, ..}
+			| TaskmasterEvent::Exited{program, ..}
+			| TaskmasterEvent::Backoff{program, ..}
+			| TaskmasterEvent::Fatal{program, ..}
+			| TaskmasterEvent::Stopped{program, ..}
+			| TaskmasterEvent::Restarting{program, ..} => Some(program),
+			_ => None,
+		}
+	}
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum TaskmasterDaemonResult {
+	Hello(u32), // negotiated protocol version, must stay the first variant
+
 	Success,
 	Ok(String),
 	Raw(String),
 	Err(String),
-}
\ No newline at end of file
+
+	Event(TaskmasterEvent), // pushed after a subscribe
+	Snapshot(Vec<ProcessSnapshot>),
+	Output{stream: LogStream, data: Vec<u8>}, // pushed after an attach
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProcessSnapshot {
+	pub task_id: usize,
+	pub program: String,
+	pub process: usize,
+	pub state: String, // RUNNING, STARTING, EXITED...
+	pub pid: Option<u32>,
+	pub uptime_sec: Option<u64>,
+	pub restarts: u64,
+	pub cpu_percent: Option<f64>, // unknown until the process has been sampled
+	pub rss_bytes: Option<u64>,
+}
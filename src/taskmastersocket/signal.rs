@@ -0,0 +1,101 @@
+// Signals of the platform taskmaster was built for, by name without the SIG prefix
+pub const SIGNALS: &[(&str, libc::c_int)] = &[
+	("HUP", libc::SIGHUP),
+	("INT", libc::SIGINT),
+	("QUIT", libc::SIGQUIT),
+	("ILL", libc::SIGILL),
+	("TRAP", libc::SIGTRAP),
+	("ABRT", libc::SIGABRT),
+	("IOT", libc::SIGIOT),
+	("BUS", libc::SIGBUS),
+	#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd"))]
+	("EMT", libc::SIGEMT),
+	("FPE", libc::SIGFPE),
+	("KILL", libc::SIGKILL),
+	("USR1", libc::SIGUSR1),
+	("SEGV", libc::SIGSEGV),
+	("USR2", libc::SIGUSR2),
+	("PIPE", libc::SIGPIPE),
+	("ALRM", libc::SIGALRM),
+	("TERM", libc::SIGTERM),
+	#[cfg(any(target_os = "linux", target_os = "android"))]
+	("STKFLT", libc::SIGSTKFLT),
+	("CHLD", libc::SIGCHLD),
+	("CONT", libc::SIGCONT),
+	("STOP", libc::SIGSTOP),
+	("TSTP", libc::SIGTSTP),
+	("TTIN", libc::SIGTTIN),
+	("TTOU", libc::SIGTTOU),
+	("URG", libc::SIGURG),
+	("XCPU", libc::SIGXCPU),
+	("XFSZ", libc::SIGXFSZ),
+	("VTALRM", libc::SIGVTALRM),
+	("PROF", libc::SIGPROF),
+	("WINCH", libc::SIGWINCH),
+	("IO", libc::SIGIO),
+	#[cfg(any(target_os = "linux", target_os = "android"))]
+	("POLL", libc::SIGPOLL),
+	#[cfg(any(target_os = "linux", target_os = "android"))]
+	("PWR", libc::SIGPWR),
+	#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd"))]
+	("INFO", libc::SIGINFO),
+	("SYS", libc::SIGSYS),
+];
+
+// Real-time signals, only Linux has them
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn realtime() -> Option<(libc::c_int, libc::c_int)> {
+	Some((libc::SIGRTMIN(), libc::SIGRTMAX()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn realtime() -> Option<(libc::c_int, libc::c_int)> {
+	None
+}
+
+fn max_signal() -> libc::c_int {
+	realtime().map_or_else(|| SIGNALS.iter().map(|(_, signal)| *signal).max().unwrap_or(0), |(_, max)| max)
+}
+
+// "TERM", "SIGTERM", "15", and on Linux "RTMIN+2" or "RTMAX-1", in any case
+pub fn parse_signal(signal: &str) -> Option<libc::c_int> {
+	let signal = signal.trim().to_ascii_uppercase();
+	let name = signal.strip_prefix("SIG").unwrap_or(&signal);
+
+	if let Ok(number) = name.parse::<libc::c_int>() {
+		return (1..=max_signal()).contains(&number).then_some(number);
+	}
+	if let Some(&(_, signal)) = SIGNALS.iter().find(|(known, _)| *known == name) {
+		return Some(signal);
+	}
+
+	let (min, max) = realtime()?;
+	let signal = match name.split_once(['+', '-']) {
+		None if name == "RTMIN" => min,
+		None if name == "RTMAX" => max,
+		Some(("RTMIN", n)) if name.contains('+') => min + n.parse::<libc::c_int>().ok()?,
+		Some(("RTMAX", n)) if name.contains('-') => max - n.parse::<libc::c_int>().ok()?,
+		_ => return None,
+	};
+	(min..=max).contains(&signal).then_some(signal)
+}
+
+pub fn signal_name(signal: libc::c_int) -> Option<String> {
+	if let Some((name, _)) = SIGNALS.iter().find(|(_, known)| *known == signal) {
+		return Some((*name).to_owned());
+	}
+	match realtime()? {
+		(min, _) if signal == min => Some("RTMIN".to_owned()),
+		(min, max) if (min..=max).contains(&signal) => Some(format!("RTMIN+{}", signal - min)),
+		_ => None,
+	}
+}
+
+// What parse_signal accepts, for completion
+pub fn signal_names() -> Vec<String> {
+	let mut names: Vec<String> = SIGNALS.iter().map(|(name, _)| (*name).to_owned()).collect();
+	if realtime().is_some() {
+		names.extend(["RTMIN".to_owned(), "RTMAX".to_owned()]);
+	}
+	names
+}
@@ -0,0 +1,68 @@
+#![cfg(feature = "async")]
+
+use std::{future::{poll_fn, Future}, pin::Pin, time::Duration};
+
+use futures_core::Stream;
+use taskmastersocket::{
+	AsyncTaskmasterClient, ClientError, TaskmasterDaemonRequest, TaskmasterDaemonResult, TaskmasterEvent,
+	write_frame, PROTOCOL_VERSION
+};
+
+mod common;
+use common::{fake_daemon, greet};
+
+fn block_on<F: Future>(future: F) -> F::Output {
+	tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.build()
+		.unwrap()
+		.block_on(future)
+}
+
+#[test]
+fn handshake_then_typed_requests() {
+	let path = fake_daemon("async-typed", |request, _| greet(&request).or(Some(match request {
+		TaskmasterDaemonRequest::InfoTask(1) => TaskmasterDaemonResult::Raw("info\n".to_owned()),
+		_ => TaskmasterDaemonResult::Err("Task not found".to_owned()),
+	})));
+
+	block_on(async {
+		let mut client = AsyncTaskmasterClient::connect(&path).await.unwrap();
+		assert_eq!(client.protocol_version(), PROTOCOL_VERSION);
+		assert_eq!(client.info(1).await.unwrap(), "info\n");
+		assert!(matches!(client.stop(2).await, Err(ClientError::Daemon(_))));
+	});
+}
+
+#[test]
+fn silent_daemon_times_out() {
+	let path = fake_daemon("async-silent", |request, _| greet(&request));
+
+	block_on(async {
+		let mut client = AsyncTaskmasterClient::connect_timeout(&path, Some(Duration::from_millis(50))).await.unwrap();
+		assert!(matches!(client.status().await, Err(ClientError::Timeout)));
+		assert!(matches!(client.status().await, Err(ClientError::Desynchronized)));
+	});
+}
+
+#[test]
+fn event_stream_ends_when_daemon_closes() {
+	let path = fake_daemon("async-subscribe", |request, stream| {
+		if let TaskmasterDaemonRequest::Subscribe(Some(program)) = request {
+			write_frame(stream, &TaskmasterDaemonResult::Success).unwrap();
+			write_frame(stream, &TaskmasterDaemonResult::Event(TaskmasterEvent::Stopped{program, process: 0})).unwrap();
+			stream.shutdown(std::net::Shutdown::Both).unwrap();
+			return None;
+		}
+		greet(&request)
+	});
+
+	block_on(async {
+		let client = AsyncTaskmasterClient::connect(&path).await.unwrap();
+		let mut events = client.subscribe(Some("nginx")).await.unwrap();
+
+		let first = poll_fn(|cx| Pin::new(&mut events).poll_next(cx)).await;
+		assert_eq!(first.unwrap().unwrap(), TaskmasterEvent::Stopped{program: "nginx".to_owned(), process: 0});
+		assert!(poll_fn(|cx| Pin::new(&mut events).poll_next(cx)).await.is_none());
+	});
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use taskmastersocket::{
+	TaskmasterClient, ClientError, TaskmasterDaemonRequest, TaskmasterDaemonResult, TaskmasterEvent, LogStream,
+	write_frame, PROTOCOL_VERSION
+};
+
+mod common;
+use common::{fake_daemon, greet};
+
+#[test]
+fn handshake_then_typed_requests() {
+	let path = fake_daemon("typed", |request, _| greet(&request).or(Some(match request {
+		TaskmasterDaemonRequest::Status => TaskmasterDaemonResult::Raw("all good\n".to_owned()),
+		TaskmasterDaemonRequest::StartTask(1) => TaskmasterDaemonResult::Success,
+		_ => TaskmasterDaemonResult::Err("Task not found".to_owned()),
+	})));
+
+	let mut client = TaskmasterClient::connect(&path).unwrap();
+	assert_eq!(client.protocol_version(), PROTOCOL_VERSION);
+	assert_eq!(client.status().unwrap(), "all good\n");
+	client.start(1).unwrap();
+	assert!(matches!(client.start(2), Err(ClientError::Daemon(err)) if err == "Task not found"));
+}
+
+#[test]
+fn refused_hello_is_incompatible() {
+	let path = fake_daemon("refused", |_, _| Some(TaskmasterDaemonResult::Err("too old".to_owned())));
+
+	assert!(matches!(TaskmasterClient::connect(&path), Err(ClientError::Incompatible(_))));
+}
+
+#[test]
+fn silent_daemon_times_out() {
+	let path = fake_daemon("silent", |request, _| greet(&request));
+
+	let mut client = TaskmasterClient::connect_timeout(&path, Some(Duration::from_millis(50))).unwrap();
+	assert!(matches!(client.status(), Err(ClientError::Timeout)));
+	// A late answer to the first request must not be taken for the answer to the next one
+	assert!(matches!(client.status(), Err(ClientError::Desynchronized)));
+}
+
+#[test]
+fn subscription_yields_events_until_closed() {
+	let path = fake_daemon("subscribe", |request, stream| {
+		if let TaskmasterDaemonRequest::Subscribe(_) = request {
+			write_frame(stream, &TaskmasterDaemonResult::Success).unwrap();
+			write_frame(stream, &TaskmasterDaemonResult::Event(TaskmasterEvent::ConfigReloaded)).unwrap();
+			stream.shutdown(std::net::Shutdown::Both).unwrap();
+			return None;
+		}
+		greet(&request)
+	});
+
+	let events: Vec<_> = TaskmasterClient::connect(&path).unwrap()
+		.subscribe(None).unwrap()
+		.collect::<Result<_, _>>().unwrap();
+	assert_eq!(events, vec![TaskmasterEvent::ConfigReloaded]);
+}
+
+#[test]
+fn attachment_echoes_input_until_detached() {
+	let path = fake_daemon("attach", |request, _| match request {
+		TaskmasterDaemonRequest::Attach{id: 1, process: 0} => Some(TaskmasterDaemonResult::Ok("Attached".to_owned())),
+		TaskmasterDaemonRequest::Input(data) => Some(TaskmasterDaemonResult::Output{stream: LogStream::Stdout, data}),
+		request => greet(&request),
+	});
+
+	let attachment = TaskmasterClient::connect(&path).unwrap().attach(1, 0).unwrap();
+	assert_eq!(attachment.message, "Attached");
+
+	let mut input = attachment.input().unwrap();
+	input.send(b"hello\n").unwrap();
+	input.detach().unwrap();
+
+	let output: Vec<_> = attachment.collect::<Result<_, _>>().unwrap();
+	assert_eq!(output, vec![(LogStream::Stdout, b"hello\n".to_vec())]);
+}
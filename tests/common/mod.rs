@@ -0,0 +1,31 @@
+use std::{os::unix::net::{UnixListener, UnixStream}, path::PathBuf, thread};
+
+use taskmastersocket::{TaskmasterDaemonRequest, TaskmasterDaemonResult, read_frame, write_frame};
+
+// Serve a single connection, answering each request with the given closure
+pub fn fake_daemon<F>(name: &str, mut answer: F) -> PathBuf
+where
+	F: FnMut(TaskmasterDaemonRequest, &mut UnixStream) -> Option<TaskmasterDaemonResult> + Send + 'static
+{
+	let path = std::env::temp_dir().join(format!("taskmaster-{}-{name}.sock", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let listener = UnixListener::bind(&path).unwrap();
+
+	thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		while let Ok(request) = read_frame::<_, TaskmasterDaemonRequest>(&mut stream) {
+			if let Some(result) = answer(request, &mut stream) {
+				write_frame(&mut stream, &result).unwrap();
+			}
+		}
+	});
+
+	path
+}
+
+pub fn greet(request: &TaskmasterDaemonRequest) -> Option<TaskmasterDaemonResult> {
+	match request {
+		TaskmasterDaemonRequest::Hello(version) => Some(TaskmasterDaemonResult::Hello(*version)),
+		_ => None,
+	}
+}
@@ -0,0 +1,17 @@
+use taskmastersocket::{format_duration, format_size};
+
+#[test]
+fn sizes_use_the_largest_unit() {
+	assert_eq!(format_size(512), "512B");
+	assert_eq!(format_size(1536), "1.5K");
+	assert_eq!(format_size(300 << 20), "300.0M");
+	assert_eq!(format_size(3 << 30), "3.0G");
+}
+
+#[test]
+fn durations_use_two_units() {
+	assert_eq!(format_duration(42), "42s");
+	assert_eq!(format_duration(61), "1m01s");
+	assert_eq!(format_duration(7260), "2h01m");
+	assert_eq!(format_duration(90000), "1d01h");
+}
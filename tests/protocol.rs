@@ -0,0 +1,150 @@
+use std::io::{Cursor, ErrorKind};
+
+use taskmastersocket::{
+	TaskmasterDaemonRequest, TaskmasterDaemonResult, TaskmasterEvent, LogStream, FrameError,
+	read_frame, write_frame, negotiate_version,
+	MAX_FRAME_LEN, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+};
+
+fn encode<T: serde::Serialize>(value: &T) -> Vec<u8> {
+	let mut buf = Vec::new();
+	write_frame(&mut buf, value).unwrap();
+	buf
+}
+
+fn raw_frame(payload: &[u8]) -> Vec<u8> {
+	let mut buf = (payload.len() as u32).to_be_bytes().to_vec();
+	buf.extend_from_slice(payload);
+	buf
+}
+
+#[test]
+fn frame_is_length_prefixed() {
+	let buf = encode(&TaskmasterDaemonRequest::Status);
+
+	let len = u32::from_be_bytes(buf[..4].try_into().unwrap());
+	assert_eq!(len as usize, buf.len() - 4);
+}
+
+#[test]
+fn request_round_trip() {
+	let buf = encode(&TaskmasterDaemonRequest::LoadFile("/tmp/config.yaml".to_owned()));
+
+	match read_frame(&mut Cursor::new(buf)).unwrap() {
+		TaskmasterDaemonRequest::LoadFile(path) => assert_eq!(path, "/tmp/config.yaml"),
+		other => panic!("unexpected request {other:?}"),
+	}
+}
+
+#[test]
+fn result_round_trip() {
+	let buf = encode(&TaskmasterDaemonResult::Err("Task not found".to_owned()));
+
+	match read_frame(&mut Cursor::new(buf)).unwrap() {
+		TaskmasterDaemonResult::Err(err) => assert_eq!(err, "Task not found"),
+		other => panic!("unexpected result {other:?}"),
+	}
+}
+
+// Every version must be able to decode the hello of every other version
+#[test]
+fn hello_is_the_first_variant() {
+	let mut expected = 0u32.to_le_bytes().to_vec();
+	expected.extend_from_slice(&7u32.to_le_bytes());
+
+	assert_eq!(encode(&TaskmasterDaemonRequest::Hello(7)), raw_frame(&expected));
+	assert_eq!(encode(&TaskmasterDaemonResult::Hello(7)), raw_frame(&expected));
+}
+
+// Reordering variants breaks peers built from another commit, append new ones instead
+#[test]
+fn variant_indexes_are_stable() {
+	let requests = [
+		(TaskmasterDaemonRequest::Status, 1u32),
+		(TaskmasterDaemonRequest::Reload, 2),
+		(TaskmasterDaemonRequest::Restart, 3),
+		(TaskmasterDaemonRequest::Stop, 4),
+		(TaskmasterDaemonRequest::StartTask(0), 5),
+		(TaskmasterDaemonRequest::StopTask(0), 6),
+		(TaskmasterDaemonRequest::RestartTask(0), 7),
+		(TaskmasterDaemonRequest::InfoTask(0), 8),
+		(TaskmasterDaemonRequest::LoadFile(String::new()), 9),
+		(TaskmasterDaemonRequest::UnloadFile(String::new()), 10),
+		(TaskmasterDaemonRequest::LogsTask{id: 0, stream: LogStream::Stdout, lines: 0}, 11),
+		(TaskmasterDaemonRequest::Subscribe(None), 12),
+		(TaskmasterDaemonRequest::StatusTree, 13),
+		(TaskmasterDaemonRequest::InfoTaskTree(0), 14),
+		(TaskmasterDaemonRequest::Snapshot, 15),
+		(TaskmasterDaemonRequest::SendTask{id: 0, process: None, data: Vec::new()}, 16),
+		(TaskmasterDaemonRequest::Attach{id: 0, process: 0}, 17),
+		(TaskmasterDaemonRequest::Input(Vec::new()), 18),
+		(TaskmasterDaemonRequest::SignalTask{id: 0, process: None, signal: String::new()}, 19),
+		(TaskmasterDaemonRequest::Upgrade, 20),
+		(TaskmasterDaemonRequest::RestartTaskRolling{id: 0, batch: 1, wait_ready: false}, 21),
+		(TaskmasterDaemonRequest::ScaleTask{program: String::new(), numprocs: None}, 22),
+		(TaskmasterDaemonRequest::StartProcess{id: 0, process: 0}, 23),
+		(TaskmasterDaemonRequest::StopProcess{id: 0, process: 0}, 24),
+		(TaskmasterDaemonRequest::RestartProcess{id: 0, process: 0}, 25),
+		(TaskmasterDaemonRequest::LogsProcess{id: 0, process: 0, stream: LogStream::Stdout, lines: 0}, 26),
+	];
+	for (request, index) in requests {
+		assert_eq!(encode(&request)[4..8], index.to_le_bytes(), "{request:?}");
+	}
+
+	let results = [
+		(TaskmasterDaemonResult::Success, 1u32),
+		(TaskmasterDaemonResult::Ok(String::new()), 2),
+		(TaskmasterDaemonResult::Raw(String::new()), 3),
+		(TaskmasterDaemonResult::Err(String::new()), 4),
+		(TaskmasterDaemonResult::Event(TaskmasterEvent::ConfigReloaded), 5),
+		(TaskmasterDaemonResult::Snapshot(Vec::new()), 6),
+		(TaskmasterDaemonResult::Output{stream: LogStream::Stdout, data: Vec::new()}, 7),
+	];
+	for (result, index) in results {
+		assert_eq!(encode(&result)[4..8], index.to_le_bytes(), "{result:?}");
+	}
+}
+
+#[test]
+fn unknown_variant_keeps_the_stream_usable() {
+	let mut buf = raw_frame(&u32::MAX.to_le_bytes());
+	buf.extend(encode(&TaskmasterDaemonRequest::Status));
+	let mut stream = Cursor::new(buf);
+
+	assert!(matches!(
+		read_frame::<_, TaskmasterDaemonRequest>(&mut stream),
+		Err(FrameError::Decode(_))
+	));
+	assert!(matches!(
+		read_frame::<_, TaskmasterDaemonRequest>(&mut stream),
+		Ok(TaskmasterDaemonRequest::Status)
+	));
+}
+
+#[test]
+fn oversized_frame_is_refused() {
+	let buf = (MAX_FRAME_LEN + 1).to_be_bytes().to_vec();
+
+	assert!(matches!(
+		read_frame::<_, TaskmasterDaemonRequest>(&mut Cursor::new(buf)),
+		Err(FrameError::TooLarge(len)) if len == MAX_FRAME_LEN + 1
+	));
+}
+
+#[test]
+fn truncated_frame_is_an_io_error() {
+	let mut buf = encode(&TaskmasterDaemonRequest::LoadFile("/tmp/config.yaml".to_owned()));
+	buf.truncate(buf.len() - 1);
+
+	match read_frame::<_, TaskmasterDaemonRequest>(&mut Cursor::new(buf)) {
+		Err(FrameError::Io(err)) => assert_eq!(err.kind(), ErrorKind::UnexpectedEof),
+		other => panic!("unexpected result {other:?}"),
+	}
+}
+
+#[test]
+fn version_negotiation() {
+	assert_eq!(negotiate_version(PROTOCOL_VERSION), Some(PROTOCOL_VERSION));
+	assert_eq!(negotiate_version(PROTOCOL_VERSION + 1), Some(PROTOCOL_VERSION));
+	assert_eq!(negotiate_version(MIN_PROTOCOL_VERSION - 1), None);
+}
@@ -0,0 +1,38 @@
+use taskmastersocket::{parse_signal, signal_name, signal_names, SIGNALS};
+
+#[test]
+fn names_come_from_the_platform() {
+	assert_eq!(parse_signal("USR1"), Some(libc::SIGUSR1));
+	assert_eq!(parse_signal("USR2"), Some(libc::SIGUSR2));
+	assert_eq!(parse_signal("STOP"), Some(libc::SIGSTOP));
+	assert_eq!(parse_signal("CHLD"), Some(libc::SIGCHLD));
+}
+
+#[test]
+fn prefix_case_and_numbers() {
+	assert_eq!(parse_signal("SIGTERM"), Some(libc::SIGTERM));
+	assert_eq!(parse_signal("term"), Some(libc::SIGTERM));
+	assert_eq!(parse_signal("15"), Some(15));
+	assert_eq!(parse_signal("0"), None);
+	assert_eq!(parse_signal("1000"), None);
+	assert_eq!(parse_signal("NOPE"), None);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn realtime_signals() {
+	assert_eq!(parse_signal("RTMIN"), Some(libc::SIGRTMIN()));
+	assert_eq!(parse_signal("SIGRTMIN+2"), Some(libc::SIGRTMIN() + 2));
+	assert_eq!(parse_signal("RTMAX-1"), Some(libc::SIGRTMAX() - 1));
+	assert_eq!(parse_signal("RTMIN+100"), None);
+	assert_eq!(signal_name(libc::SIGRTMIN() + 3).as_deref(), Some("RTMIN+3"));
+}
+
+#[test]
+fn every_name_round_trips() {
+	for (name, signal) in SIGNALS {
+		assert_eq!(parse_signal(name), Some(*signal), "{name}");
+		assert_eq!(parse_signal(&signal_name(*signal).unwrap()), Some(*signal), "{name}");
+	}
+	assert!(signal_names().iter().all(|name| parse_signal(name).is_some()));
+}